@@ -37,6 +37,35 @@ in {
       '';
     };
 
+    soa_serial = mkOption {
+      type = types.enum [ "counter" "date" ];
+      default = "counter";
+      description = ''
+        Scheme for the SOA serial. "counter" increments a serial persisted
+        in the TXT store each time it changes, "date" serves the current date.
+      '';
+    };
+
+    soa_timers = mkOption {
+      type = types.attrsOf types.ints.unsigned;
+      default = { };
+      example = { refresh = 3600; };
+      description = ''
+        SOA refresh, retry, expire and minimum timers in seconds. Omitted
+        timers use the defaults.
+      '';
+    };
+
+    ttls = mkOption {
+      type = types.attrsOf types.ints.unsigned;
+      default = { };
+      example = { static = 86400; };
+      description = ''
+        Record TTLs in seconds by type: txt, static (A/AAAA/CNAME/CAA), ns
+        and soa. Omitted TTLs use the defaults.
+      '';
+    };
+
     # TODO(XXX): Make state optional.
     txt_store_state_path = mkOption {
       type = types.str;
@@ -47,6 +76,17 @@ in {
       '';
     };
 
+    txt_max_age = mkOption {
+      type = types.nullOr types.numbers.positive;
+      default = null;
+      example = 3600;
+      description = ''
+        Maximum age of dynamic TXT record values, expressed in seconds.
+        Older values are no longer served. If null, values are served
+        until replaced by newer updates.
+      '';
+    };
+
     api_addr = mkOption {
       type = types.str;
       example = "10.233.1.2";
@@ -64,6 +104,16 @@ in {
       '';
     };
 
+    metrics_bind_addr = mkOption {
+      type = types.nullOr types.str;
+      default = null;
+      example = "10.233.1.2:9100";
+      description = ''
+        Separate bind address (including port) for the /metrics endpoint.
+        If null, metrics are served on the HTTP API bind address.
+      '';
+    };
+
     api_timeout = mkOption {
       type = types.numbers.positive;
       default = 120;
@@ -81,6 +131,122 @@ in {
       '';
     };
 
+    dns_tls_bind_addr = mkOption {
+      type = types.nullOr types.str;
+      default = null;
+      example = "10.233.1.2:853";
+      description = ''
+        Bind address (including port) for DNS-over-TLS. Requires a package
+        built with the dns-over-tls feature. If null, DNS-over-TLS is not
+        served.
+      '';
+    };
+
+    dns_tls_cert_path = mkOption {
+      type = types.nullOr types.str;
+      default = null;
+      example = "/var/lib/acme/ns1.pki.example.com/fullchain.pem";
+      description = ''
+        Path to the PEM certificate chain for DNS-over-TLS. Reloaded when it
+        changes.
+      '';
+    };
+
+    dns_tls_key_path = mkOption {
+      type = types.nullOr types.str;
+      default = null;
+      example = "/var/lib/acme/ns1.pki.example.com/key.pem";
+      description = ''
+        Path to the PEM private key for DNS-over-TLS. Reloaded when it
+        changes.
+      '';
+    };
+
+    dns_max_udp_payload = mkOption {
+      type = types.ints.between 512 65535;
+      default = 1232;
+      description = ''
+        Maximum size of DNS responses over UDP to EDNS(0) clients, in bytes.
+      '';
+    };
+
+    rate_limit = mkOption {
+      type = types.nullOr (types.submodule {
+        options = {
+          responses_per_second = mkOption {
+            type = types.ints.unsigned;
+            default = 10;
+            description = "Answers (including NODATA) per second for each client network.";
+          };
+          nxdomains_per_second = mkOption {
+            type = types.ints.unsigned;
+            default = 5;
+            description = "NXDOMAIN responses per second for each client network.";
+          };
+          errors_per_second = mkOption {
+            type = types.ints.unsigned;
+            default = 5;
+            description = "Error responses per second for each client network.";
+          };
+          slip = mkOption {
+            type = types.ints.unsigned;
+            default = 2;
+            description = "Send every Nth rate limited response truncated instead of dropping it.";
+          };
+          ipv4_prefix_len = mkOption {
+            type = types.ints.between 0 32;
+            default = 24;
+            description = "Prefix length IPv4 clients are grouped by.";
+          };
+          ipv6_prefix_len = mkOption {
+            type = types.ints.between 0 128;
+            default = 56;
+            description = "Prefix length IPv6 clients are grouped by.";
+          };
+          exempt = mkOption {
+            type = types.listOf types.str;
+            default = [ ];
+            description = "CIDR networks whose responses are never rate limited.";
+          };
+        };
+      });
+      default = null;
+      description = ''
+        Rate limits for DNS responses sent over UDP, to prevent reflection
+        attacks. If null, responses aren't rate limited.
+      '';
+    };
+
+    dnssec = mkOption {
+      type = types.nullOr (types.submodule {
+        options = {
+          algorithm = mkOption {
+            type = types.enum [ "ecdsap256sha256" "ecdsap384sha384" "ed25519" ];
+            default = "ecdsap256sha256";
+            description = "Signing algorithm of the KSK and ZSK.";
+          };
+          ksk_path = mkOption {
+            type = types.str;
+            description = "Path to the PKCS#8 private key of the key signing key.";
+          };
+          zsk_path = mkOption {
+            type = types.str;
+            description = "Path to the PKCS#8 private key of the zone signing key.";
+          };
+          signature_validity = mkOption {
+            type = types.ints.between 3600 4294967295;
+            default = 604800;
+            description = "Validity period of RRSIG records, in seconds.";
+          };
+        };
+      });
+      default = null;
+      description = ''
+        Keys used to sign DNS responses online with DNSSEC. Requires a package
+        built with the dnssec feature. If null, responses aren't signed.
+      '';
+    };
+
     dns_udp_addr = mkOption {
       type = types.str;
       default = "0.0.0.0";
@@ -108,15 +274,91 @@ in {
       '';
     };
 
+    shutdown_timeout = mkOption {
+      type = types.numbers.positive;
+      default = 10;
+      description = ''
+        Maximum duration to wait for in-flight requests to complete at
+        shutdown, expressed in seconds.
+      '';
+    };
+
     acl = mkOption {
       type = types.submodule {
         freeformType = types.attrsOf (types.listOf types.str);
       };
       default = { };
-      example = { "127.0.0.0/24" = [ "subdomain_a" "subdomain_b" ]; };
+      example = { "127.0.0.0/24" = [ "subdomain_a" "subdomain_b" "*.web" "**.team-a" ]; };
       description = ''
         A map of CIDR networks and subdomains IPs within that network 
-        can updated TXT records for.'';
+        can updated TXT records for. Subdomains may be patterns: "*" matches
+        a label, "{n}" matches digits within a label, and a leading "**"
+        matches any number of labels. Networks may also be WireGuard peer
+        public keys, standing for the allowed IPs of the peer in
+        wireguard_peers_path.'';
+    };
+
+    wireguard_peers_path = mkOption {
+      type = types.nullOr types.str;
+      default = null;
+      example = "/etc/wireguard/wg0.conf";
+      description = ''
+        Path to a WireGuard interface config file, or a file with the output
+        of `wg show <interface> dump`, that the allowed IPs of WireGuard peers
+        in the acl are read from. Re-read when the config is reloaded.'';
+    };
+
+    zones = mkOption {
+      type = types.listOf types.attrs;
+      default = [ ];
+      example = [{
+        domain = "lab.example.org";
+        ns_domain = "ns1.pki.example.com";
+        ns_admin = "dns-admin@example.com";
+        acl = { "10.0.0.0/8" = [ "test" ]; };
+        addrs = { };
+        ns_records = { "lab.example.org" = [ "ns1.pki.example.com" ]; };
+      }];
+      description = ''
+        Additional zones served by the same instance, each with its own
+        domain, ns_domain, ns_admin, soa_timers, ttls, acl, addrs, ns_records,
+        cnames, caa_records and tsig_keys. The zone configured by the top level
+        options is served as the first zone. Queries are answered from the zone
+        with the longest domain the query name is within.'';
+    };
+
+    registration = mkOption {
+      type = types.bool;
+      default = false;
+      description = ''
+        Enable the /register API endpoint, and X-Api-User/X-Api-Key
+        authentication for the /update API endpoint.
+      '';
+    };
+
+    tsig_keys = mkOption {
+      type = types.attrsOf (types.submodule {
+        options = {
+          algorithm = mkOption {
+            type = types.enum [ "hmac-sha224" "hmac-sha256" "hmac-sha384" "hmac-sha512" ];
+            default = "hmac-sha256";
+            description = "TSIG key HMAC algorithm.";
+          };
+          secret = mkOption {
+            type = types.str;
+            description = "Base64 encoded TSIG key secret.";
+          };
+          subdomains = mkOption {
+            type = types.listOf types.str;
+            description = "Subdomains UPDATE messages signed with the key may change TXT records for.";
+          };
+        };
+      });
+      default = { };
+      description = ''
+        A map of TSIG key names to the keys used to authenticate RFC 2136
+        UPDATE messages. Note that the secrets are written to the Nix store.
+      '';
     };
 
     addrs = mkOption {
@@ -145,6 +387,64 @@ in {
         returned for NS lookups. 
       '';
     };
+
+    transfer_allowed = mkOption {
+      type = types.listOf types.str;
+      default = [ ];
+      example = [ "10.0.0.53/32" ];
+      description = ''
+        CIDR networks allowed to request AXFR zone transfers over TCP.
+      '';
+    };
+
+    notify_addrs = mkOption {
+      type = types.listOf types.str;
+      default = [ ];
+      example = [ "10.0.0.53:53" ];
+      description = ''
+        Secondary nameserver addresses sent a DNS NOTIFY when TXT records change.
+      '';
+    };
+
+    caa_records = mkOption {
+      type = types.attrsOf (types.listOf (types.submodule {
+        options = {
+          critical = mkOption {
+            type = types.bool;
+            default = false;
+            description = "Issuer critical flag.";
+          };
+          tag = mkOption {
+            type = types.enum [ "issue" "issuewild" "iodef" ];
+            description = "CAA property tag.";
+          };
+          value = mkOption {
+            type = types.str;
+            description = "CAA property value.";
+          };
+        };
+      }));
+      default = { };
+      example = {
+        "pki.example.com" = [{ tag = "issue"; value = "letsencrypt.org"; }];
+      };
+      description = ''
+        A map of fully qualified domains (the domain, or names in addrs)
+        to CAA records that should be returned for CAA lookups.
+      '';
+    };
+
+    cnames = mkOption {
+      type = types.attrsOf types.str;
+      default = { };
+      example = {
+        "_acme-challenge.host.pki.example.com" = "host.pki.example.com";
+      };
+      description = ''
+        A map of fully qualified aliases within the domain to the fully
+        qualified domain they are an alias of, returned for CNAME lookups.
+      '';
+    };
   };
 
   config = lib.mkIf cfg.enable {
@@ -154,6 +454,7 @@ in {
       serviceConfig = {
         Restart = "on-failure";
         ExecStart = "${pkgs.acmecrab}/bin/${name} /etc/${name}.json";
+        ExecReload = "${pkgs.coreutils}/bin/kill -HUP $MAINPID";
         Environment = "RUST_LOG=${name}=debug";
         DynamicUser = "yes";
         RuntimeDirectory = name;
@@ -176,8 +477,11 @@ in {
 
     environment.etc."${name}.json".source = with cfg;
       settingsFormat.generate "${name}-config.json" {
-        inherit domain ns_domain ns_admin txt_store_state_path api_timeout acl
-          addrs ns_records dns_tcp_timeout;
+        inherit domain ns_domain ns_admin soa_serial soa_timers ttls txt_store_state_path
+          api_timeout acl wireguard_peers_path zones registration txt_max_age addrs ns_records cnames caa_records
+          dns_tcp_timeout shutdown_timeout metrics_bind_addr dns_max_udp_payload
+          transfer_allowed notify_addrs tsig_keys dns_tls_bind_addr dns_tls_cert_path
+          dns_tls_key_path rate_limit dnssec;
         api_bind_addr = "${api_addr}:${toString api_port}";
         dns_udp_bind_addr = "${dns_udp_addr}:${toString dns_port}";
         dns_tcp_bind_addr = "${dns_tcp_addr}:${toString dns_port}";
@@ -0,0 +1,98 @@
+//! Registered API accounts.
+//!
+//! Accounts are created by the [`/register` API endpoint][crate::api#register-post] when
+//! [`Config::registration`][crate::config::Config::registration] is enabled. Each account is
+//! issued a random subdomain of the [`Zone::domain`][crate::config::Zone::domain] of a zone along
+//! with a username and password that clients present in the `X-Api-User` and `X-Api-Key` headers
+//! when `POST`ing the [`/update` API endpoint][crate::api#update-post].
+
+use crate::error::Error;
+use base64::engine::general_purpose;
+use base64::Engine;
+use ipnetwork::IpNetwork;
+use rand::distributions::{Alphanumeric, DistString};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::net::IpAddr;
+use trust_dns_server::client::rr::{LowerName, Name};
+use uuid::Uuid;
+
+const PASSWORD_LEN: usize = 40;
+
+/// A registered API account. Only a digest of the account password is retained.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Account {
+    /// Username presented in the `X-Api-User` header.
+    pub username: String,
+
+    /// Base64 encoded SHA-256 digest of the password presented in the `X-Api-Key` header.
+    pub password_digest: String,
+
+    /// The subdomain (relative to the [`Zone::domain`][crate::config::Zone::domain] of the zone it
+    /// was registered in) the account may update.
+    pub subdomain: LowerName,
+
+    /// The fully qualified domain name the account may update.
+    pub fqdn: LowerName,
+
+    /// Networks that updates for the account must originate from. If empty, updates are
+    /// accepted from any source IP.
+    pub allow_from: Vec<IpNetwork>,
+}
+
+impl Account {
+    /// Generate a new [`Account`] with a random username, password and subdomain of the given
+    /// domain. Returns the account and its plaintext password. The password can't be recovered
+    /// from the [`Account`] once this function returns.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::DNSError`] if the generated subdomain can't be appended to the domain.
+    pub fn generate(
+        domain: &LowerName,
+        allow_from: Vec<IpNetwork>,
+    ) -> Result<(Self, String), Error> {
+        let password = Alphanumeric.sample_string(&mut rand::thread_rng(), PASSWORD_LEN);
+        let subdomain = Name::from_ascii(Uuid::new_v4().to_string())?;
+        let fqdn = subdomain.clone().append_domain(&domain.into())?;
+        let account = Self {
+            username: Uuid::new_v4().to_string(),
+            password_digest: Self::digest(&password),
+            subdomain: subdomain.into(),
+            fqdn: fqdn.into(),
+            allow_from,
+        };
+        Ok((account, password))
+    }
+
+    /// Checks if the given password matches the account password.
+    #[must_use]
+    pub fn password_matches(&self, password: &str) -> bool {
+        let expected = self.password_digest.as_bytes();
+        let actual = Self::digest(password);
+        let actual = actual.as_bytes();
+        // Avoid short-circuiting on the first differing byte.
+        expected.len() == actual.len()
+            && expected
+                .iter()
+                .zip(actual)
+                .fold(0, |acc, (a, b)| acc | (a ^ b))
+                == 0
+    }
+
+    /// Checks if the account permits an update from the given [`IpAddr`] for the given fully
+    /// qualified domain name.
+    #[must_use]
+    pub fn update_permitted(&self, source_ip: IpAddr, fqdn: &LowerName) -> bool {
+        self.fqdn == *fqdn
+            && (self.allow_from.is_empty()
+                || self
+                    .allow_from
+                    .iter()
+                    .any(|network| network.contains(source_ip)))
+    }
+
+    fn digest(password: &str) -> String {
+        general_purpose::STANDARD.encode(Sha256::digest(password.as_bytes()))
+    }
+}
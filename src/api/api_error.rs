@@ -7,13 +7,25 @@ use serde_json::json;
 
 pub(super) struct APIError(anyhow::Error);
 
+impl APIError {
+    /// Returns the underlying [`Error`], if the API error was caused by one.
+    pub(super) fn error(&self) -> Option<&Error> {
+        self.0.downcast_ref::<Error>()
+    }
+}
+
 impl IntoResponse for APIError {
     fn into_response(self) -> Response {
         let any_err = self.0;
         let status = match any_err.downcast_ref::<Error>() {
-            Some(Error::AuthForbidden(_, _)) => StatusCode::FORBIDDEN,
+            Some(Error::AuthForbidden(_, _) | Error::ReloadForbidden(_)) => StatusCode::FORBIDDEN,
             Some(Error::NotImplemented) => StatusCode::NOT_IMPLEMENTED,
-            Some(Error::InvalidDNS01) => StatusCode::BAD_REQUEST,
+            Some(Error::UnknownZone(_)) => StatusCode::NOT_FOUND,
+            Some(Error::Unauthorized(_)) => StatusCode::UNAUTHORIZED,
+            Some(
+                Error::InvalidDNS01 | Error::InvalidRegistration(_) | Error::InvalidDoHRequest(_),
+            ) => StatusCode::BAD_REQUEST,
+            Some(Error::InvalidDoHContentType) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
             Some(Error::JsonExtractorRejection(err)) => match err {
                 JsonRejection::JsonDataError(_) => StatusCode::UNPROCESSABLE_ENTITY,
                 JsonRejection::JsonSyntaxError(_) => StatusCode::BAD_REQUEST,
@@ -8,14 +8,36 @@
 //!
 //! ## `/register` (POST)
 //!
-//!   Returns HTTP 501 (Not Implemented).
+//!   Returns HTTP 501 (Not Implemented) unless
+//!   [`Config::registration`][crate::config::Config::registration] is enabled.
 //!
-//!   This endpoint is not provided by ACME Crab. It is
-//!   expected that users configure their ACME client to use the ACME DNS API provided by
-//!   ACME Crab as if a user account had already been registered. No username/password
-//!   header authentication is performed by ACME Crab's update endpoint. ACLs are handled
+//!   By default it is expected that users configure their ACME client to use the ACME DNS API
+//!   provided by ACME Crab as if a user account had already been registered. ACLs are handled
 //!   with [cryptokey routing].
 //!
+//!   When registration is enabled, accepts an optional JSON request body of the form:
+//!
+//!   ```json
+//!   { "allowfrom": [ "10.0.0.0/24" ] }
+//!   ```
+//!
+//!   And returns HTTP 201 (Created) with a JSON response body of the form:
+//!
+//!   ```json
+//!   {
+//!     "username": "c36f50e8-4632-44f0-83fe-e070fef28a10",
+//!     "password": "htB9mR9DYgcu9bX3afHF62erXaH2TS7bg9KW3F7Z",
+//!     "fulldomain": "8e5700ea-a4bf-41c7-8a77-e990661dcc6a.pki.example.com",
+//!     "subdomain": "8e5700ea-a4bf-41c7-8a77-e990661dcc6a",
+//!     "allowfrom": [ "10.0.0.0/24" ]
+//!   }
+//!   ```
+//!
+//!   The `username` and `password` are used as the `X-Api-User` and `X-Api-Key` headers for
+//!   updating the `subdomain`. If `allowfrom` is not empty, updates must also originate from
+//!   a source IP within one of the listed networks. The request body may also name the `zone`
+//!   the subdomain is issued in, as for [updates](#update-post).
+//!
 //!   [cryptokey routing]: https://www.wireguard.com/#cryptokey-routing
 //!
 //! ## `/update` (POST)
@@ -26,9 +48,22 @@
 //!   { "subdomain": "test", "txt": "XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX" }
 //!   ```
 //!  
-//!  Where `subdomain` is a subdomain of the ACME Crab domain, registered in the configuration
-//!  ACL. The client `POST`ing the update must have a source IP address within a network specified
-//!  in the ACL entry for the `subdomain`.
+//!  Where `subdomain` is a subdomain of the ACME Crab domain, registered in (or matching a
+//!  [pattern][crate::config::SubdomainPattern] in) the configuration ACL. The client `POST`ing
+//!  the update must have a source IP address within a network (or the allowed IPs of a
+//!  [`AclKey::WireGuardPeer`][crate::config::AclKey::WireGuardPeer]) specified in the ACL entry for
+//!  the `subdomain`.
+//!
+//!  When ACME Crab serves [multiple zones][crate::dns#multiple-zones], the request body may name
+//!  the zone with a `zone` key, e.g. `"zone": "lab.example.org"`, and the `subdomain` and ACL of
+//!  that zone are used. If `zone` is omitted the first configured zone is used. Unknown zones
+//!  return HTTP 404 (Not Found).
+//!
+//!  If [`Config::registration`][crate::config::Config::registration] is enabled and the request
+//!  has `X-Api-User` and `X-Api-Key` headers, the credentials of the registered account are
+//!  checked instead of the configuration ACL. Invalid credentials return HTTP 401
+//!  (Unauthorized). Updates with credentials that omit `zone` use the zone the account was
+//!  registered in, so acme-dns clients can update accounts registered in any zone.
 //!
 //!  The `txt` value must be a valid [RFC-8555][RFC-8555] [DNS-01] challenge response.
 //!  
@@ -39,7 +74,71 @@
 //!  ```
 //!  In the response, `txt` contains the echoed `txt` value from the client request.
 //!
+//! ## `/update` (DELETE)
+//!
+//!   Expects a JSON request body of the form:
+//!
+//!   ```json
+//!   { "subdomain": "test", "txt": "XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX" }
+//!   ```
+//!
+//!  Removes the `txt` value for the `subdomain`, e.g. from an ACME client cleanup hook after a
+//!  challenge has been validated. If `txt` is omitted, all values for the `subdomain` are removed.
+//!  Deletes name their `zone` and are authorized the same way as [updates](#update-post).
+//!
+//!  Returns HTTP 200 (OK) and a JSON response body of the form:
+//!
+//!  ```json
+//!  { "removed": 1 }
+//!  ```
+//!  In the response, `removed` is the number of TXT values that were removed.
+//!
+//! ## `/dns-query` (GET/POST)
+//!
+//!  Answers [RFC-8484][RFC-8484] DNS-over-HTTPS requests the same way as the
+//!  [DNS server][crate::dns], e.g. to debug records from a browser or an environment that only
+//!  allows HTTP. `GET` requests carry the DNS request message encoded with unpadded base64url in
+//!  the `dns` query parameter, and `POST` requests carry it as the body, with a `Content-Type` of
+//!  `application/dns-message`.
+//!
+//!  ```bash
+//!  ❯ curl -s 'http://localhost:3000/dns-query?dns=AAABAAABAAAAAAAAA3BraQdleGFtcGxlA2NvbQAAAQAB' | xxd
+//!  ```
+//!
+//!  Returns HTTP 200 (OK) and the DNS response message with a `Content-Type` of
+//!  `application/dns-message`. Responses may be cached for the lowest TTL of their records.
+//!  Requests that aren't valid DNS messages return HTTP 400 (Bad Request). Requests share the
+//!  rate limits and metrics of the DNS server.
+//!
+//!  Like the rest of the HTTP API this endpoint uses plaintext HTTP, relying on encryption at
+//!  the network layer. RFC 8484 requires DNS-over-HTTPS to use HTTPS, so clients such as
+//!  browsers must reach the endpoint through a TLS terminating reverse proxy.
+//!
+//! ## `/reload` (POST)
+//!
+//!  Re-reads and validates the configuration file, atomically replacing the configuration used
+//!  by both the HTTP API and the [DNS server][crate::dns]. Equivalent to sending ACME Crab
+//!  `SIGHUP`. If the configuration file is invalid the current configuration is kept and an
+//!  error is returned. Only clients connecting from a loopback address may reload the
+//!  configuration, other clients receive HTTP 403 (Forbidden).
+//!
+//!  Returns HTTP 200 (OK) and a JSON response body of the form:
+//!
+//!  ```json
+//!  { "changes": 1 }
+//!  ```
+//!  In the response, `changes` is the number of configuration values that changed. Each change
+//!  is logged. Changes to bind addresses, timeouts, TLS settings, DNSSEC keys and TXT store
+//!  settings don't take effect until restart.
+//!
+//! ## `/metrics` (GET)
+//!
+//!  Returns HTTP 200 (OK) and the current [metrics][crate::metrics] in the Prometheus text
+//!  format. If [`Config::metrics_bind_addr`][crate::config::Config::metrics_bind_addr] is set,
+//!  this endpoint is served on that address instead of with the rest of the HTTP API.
+//!
 //! [RFC-8555]: https://www.rfc-editor.org/rfc/rfc8555
+//! [RFC-8484]: https://www.rfc-editor.org/rfc/rfc8484
 //! [DNS-01]: https://www.rfc-editor.org/rfc/rfc8555#section-8.4
 
 mod api_error;
@@ -47,4 +146,5 @@ mod model;
 mod routes;
 pub mod server;
 
-pub use server::new;
+pub(crate) use model::valid_dns01;
+pub use server::{new, new_metrics};
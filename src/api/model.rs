@@ -1,5 +1,6 @@
 use base64::engine::general_purpose;
 use base64::{alphabet, engine, DecodeError, Engine};
+use ipnetwork::IpNetwork;
 use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
 
@@ -9,10 +10,54 @@ pub(super) struct UpdateRecordRequest {
     pub subdomain: String,
     #[serde(alias = "Txt")]
     pub txt: String,
+    #[serde(default)]
+    pub zone: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default, Ord, PartialOrd, Eq, PartialEq)]
+pub(super) struct DeleteRecordRequest {
+    #[serde(alias = "SubDomain")]
+    pub subdomain: String,
+    #[serde(alias = "Txt")]
+    pub txt: Option<String>,
+    #[serde(default)]
+    pub zone: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Default, Ord, PartialOrd, Eq, PartialEq)]
+pub(super) struct DeleteRecordResult {
+    pub removed: usize,
+}
+
+#[derive(Deserialize, Debug, Clone, Default, Eq, PartialEq)]
+pub(super) struct RegisterRequest {
+    #[serde(default)]
+    pub allowfrom: Vec<IpNetwork>,
+    #[serde(default)]
+    pub zone: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Eq, PartialEq)]
+pub(super) struct RegisterResult {
+    pub username: String,
+    pub password: String,
+    pub fulldomain: String,
+    pub subdomain: String,
+    pub allowfrom: Vec<IpNetwork>,
+}
+
+#[derive(Serialize, Debug, Clone, Default, Ord, PartialOrd, Eq, PartialEq)]
+pub(super) struct ReloadResult {
+    pub changes: usize,
+}
+
+#[derive(Deserialize, Debug, Clone, Default, Eq, PartialEq)]
+pub(super) struct DnsQueryParams {
+    pub dns: Option<String>,
 }
 
 #[derive(thiserror::Error, Debug)]
-pub(super) enum TxtValidationError {
+pub(crate) enum TxtValidationError {
     #[error("invalid encoding: {0}")]
     InvalidEncoding(DecodeError),
     #[error("invalid decoded length: found {actual} bytes, expected {expected}")]
@@ -28,16 +73,32 @@ lazy_static! {
 
 impl UpdateRecordRequest {
     pub fn valid_dns01(&self) -> Result<(), TxtValidationError> {
-        match BASE64_ENGINE.decode(&self.txt) {
-            Ok(raw) => match raw.len() {
-                DNS01_DECODED_LEN_BYTES => Ok(()),
-                _ => Err(TxtValidationError::InvalidDecodedLength {
-                    actual: raw.len(),
-                    expected: DNS01_DECODED_LEN_BYTES,
-                }),
-            },
-            Err(err) => Err(TxtValidationError::InvalidEncoding(err)),
-        }
+        valid_dns01(&self.txt)
+    }
+}
+
+impl DnsQueryParams {
+    // Returns the DNS request message from the unpadded base64url encoded `dns` parameter.
+    pub fn message(&self) -> Result<Vec<u8>, String> {
+        let dns = self.dns.as_deref().ok_or("missing dns parameter")?;
+        BASE64_ENGINE
+            .decode(dns)
+            .map_err(|err| format!("invalid dns parameter encoding: {err}"))
+    }
+}
+
+// Checks that a TXT value is a valid DNS-01 challenge response: the unpadded base64url encoding
+// of a SHA256 digest.
+pub(crate) fn valid_dns01(txt: &str) -> Result<(), TxtValidationError> {
+    match BASE64_ENGINE.decode(txt) {
+        Ok(raw) => match raw.len() {
+            DNS01_DECODED_LEN_BYTES => Ok(()),
+            _ => Err(TxtValidationError::InvalidDecodedLength {
+                actual: raw.len(),
+                expected: DNS01_DECODED_LEN_BYTES,
+            }),
+        },
+        Err(err) => Err(TxtValidationError::InvalidEncoding(err)),
     }
 }
 
@@ -1,51 +1,134 @@
+use crate::account::Account;
 use crate::api::api_error::APIError;
-use crate::api::model::{UpdateRecordRequest, UpdateRecordResult};
+use crate::api::model::{
+    DeleteRecordRequest, DeleteRecordResult, DnsQueryParams, RegisterRequest, RegisterResult,
+    ReloadResult, UpdateRecordRequest, UpdateRecordResult,
+};
 use crate::api::server::AppState;
+use crate::config::{Config, Zone};
 use crate::error::Error;
-use axum::extract::{ConnectInfo, State};
+use crate::metrics;
+use crate::metrics::UpdateOutcome;
+use crate::txt_store::DynTxtStore;
+use axum::body::Bytes;
+use axum::extract::{ConnectInfo, Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
 use axum::response::IntoResponse;
 use axum::routing::{get, post};
 use axum::{Json, Router};
 use axum_extra::extract::WithRejection;
 use serde_json::json;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::str::FromStr;
 use tower_http::timeout::TimeoutLayer;
 use tower_http::trace::TraceLayer;
-use trust_dns_server::client::rr::Name;
+use trust_dns_server::client::rr::{LowerName, Name};
+
+// The media type of DNS-over-HTTPS request and response bodies.
+const DNS_MESSAGE_CONTENT_TYPE: &str = "application/dns-message";
 
 pub(super) fn new(state: AppState) -> Router {
-    Router::new()
+    let mut router = Router::new();
+    // Metrics are served by the API server unless they have a separate bind address.
+    if state.config.current().metrics_bind_addr.is_none() {
+        router = router.route("/metrics", get(metrics));
+    }
+    router
         .route("/healthcheck", get(health_check))
         .route("/register", post(register))
-        .route("/update", post(update))
+        .route("/update", post(update).delete(delete))
+        .route("/dns-query", get(dns_query_get).post(dns_query_post))
+        .route("/reload", post(reload))
         .layer(TraceLayer::new_for_http())
-        .layer(TimeoutLayer::new(state.config.api_timeout))
+        .layer(TimeoutLayer::new(state.config.current().api_timeout))
         .with_state(state)
 }
 
+pub(super) fn new_metrics(txt_store: DynTxtStore) -> Router {
+    Router::new()
+        .route("/metrics", get(metrics))
+        .layer(TraceLayer::new_for_http())
+        .with_state(txt_store)
+}
+
 #[allow(clippy::unused_async)]
 async fn health_check() -> impl IntoResponse {
     Json(json!({"ok":"healthy"}))
 }
 
-#[allow(clippy::unused_async)]
-async fn register() -> APIError {
-    Error::NotImplemented.into()
+async fn register(
+    State(state): State<AppState>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    body: Bytes,
+) -> Result<(StatusCode, Json<RegisterResult>), APIError> {
+    let config = state.config.current();
+    if !config.registration {
+        return Err(Error::NotImplemented.into());
+    }
+
+    let payload: RegisterRequest = if body.is_empty() {
+        RegisterRequest::default()
+    } else {
+        serde_json::from_slice(&body).map_err(Error::InvalidRegistration)?
+    };
+
+    let zone = request_zone(&config, payload.zone.as_deref())?;
+    let (account, password) = Account::generate(&zone.domain, payload.allowfrom)?;
+    let result = RegisterResult {
+        username: account.username.clone(),
+        password,
+        fulldomain: account.fqdn.to_string().trim_end_matches('.').to_string(),
+        subdomain: account.subdomain.to_string(),
+        allowfrom: account.allow_from.clone(),
+    };
+    tracing::info!(
+        "registered account {} from {} for \"{}\"",
+        account.username,
+        client_addr.ip(),
+        account.fqdn
+    );
+    let mut txt_store = state.txt_store.write().await;
+    metrics::time_store_write("add_account", txt_store.add_account(account)).await?;
+    Ok((StatusCode::CREATED, Json(result)))
 }
 
 async fn update(
     State(state): State<AppState>,
     ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     WithRejection(Json(payload), _): WithRejection<Json<UpdateRecordRequest>, APIError>,
 ) -> Result<Json<UpdateRecordResult>, APIError> {
-    let client_addr = client_addr.ip();
+    let result = apply_update(&state, client_addr.ip(), &headers, payload).await;
+    metrics::api_update(match &result {
+        Ok(_) => UpdateOutcome::Accepted,
+        Err(err) => match err.error() {
+            Some(Error::Unauthorized(_)) => UpdateOutcome::Unauthorized,
+            Some(Error::AuthForbidden(_, _)) => UpdateOutcome::Forbidden,
+            Some(Error::InvalidDNS01) => UpdateOutcome::InvalidDNS01,
+            _ => UpdateOutcome::Error,
+        },
+    });
+    result
+}
+
+async fn apply_update(
+    state: &AppState,
+    client_addr: IpAddr,
+    headers: &HeaderMap,
+    payload: UpdateRecordRequest,
+) -> Result<Json<UpdateRecordResult>, APIError> {
     let subdomain: Name = Name::from_str(&payload.subdomain)?;
 
-    if !state.config.update_permitted(client_addr, &subdomain) {
-        tracing::debug!("rejected update from {client_addr} for \"{subdomain}\"",);
-        return Err(Error::AuthForbidden(client_addr, subdomain.into()).into());
-    }
+    let config = state.config.current();
+    let (zone, fqdn) = &authorize_update(
+        state,
+        &config,
+        payload.zone.as_deref(),
+        headers,
+        client_addr,
+        &subdomain,
+    )
+    .await?;
 
     match &payload.valid_dns01() {
         Err(err) => {
@@ -53,16 +136,280 @@ async fn update(
             Err(Error::InvalidDNS01.into())
         }
         Ok(_) => {
-            let domain: Name = (&state.config.domain).into();
-            let fqdn = &subdomain.append_domain(&domain)?;
             tracing::info!("accepted update from {client_addr} for \"{fqdn}\"");
-            state
-                .txt_store
-                .write()
-                .await
-                .add_txt(fqdn.into(), payload.txt.clone())
-                .await?;
+            let mut txt_store = state.txt_store.write().await;
+            metrics::time_store_write(
+                "add_txt",
+                txt_store.add_txt(fqdn.into(), payload.txt.clone()),
+            )
+            .await?;
+            drop(txt_store);
+            state.notifier.zone_changed(&zone.domain).await;
             Ok(Json(UpdateRecordResult { txt: payload.txt }))
         }
     }
 }
+
+async fn delete(
+    State(state): State<AppState>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    WithRejection(Json(payload), _): WithRejection<Json<DeleteRecordRequest>, APIError>,
+) -> Result<Json<DeleteRecordResult>, APIError> {
+    let client_addr = client_addr.ip();
+    let subdomain: Name = Name::from_str(&payload.subdomain)?;
+
+    let config = state.config.current();
+    let (zone, fqdn) = &authorize_update(
+        &state,
+        &config,
+        payload.zone.as_deref(),
+        &headers,
+        client_addr,
+        &subdomain,
+    )
+    .await?;
+
+    let mut txt_store = state.txt_store.write().await;
+    let removed = metrics::time_store_write(
+        "remove_txt",
+        txt_store.remove_txt(&fqdn.into(), payload.txt.as_deref()),
+    )
+    .await?;
+    drop(txt_store);
+    tracing::info!("accepted delete from {client_addr} for \"{fqdn}\": removed {removed}");
+    if removed > 0 {
+        state.notifier.zone_changed(&zone.domain).await;
+    }
+    Ok(Json(DeleteRecordResult { removed }))
+}
+
+async fn dns_query_get(
+    State(state): State<AppState>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Query(params): Query<DnsQueryParams>,
+) -> Result<impl IntoResponse, APIError> {
+    let message = params.message().map_err(Error::InvalidDoHRequest)?;
+    dns_query(&state, client_addr, &message).await
+}
+
+async fn dns_query_post(
+    State(state): State<AppState>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<impl IntoResponse, APIError> {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok());
+    if content_type != Some(DNS_MESSAGE_CONTENT_TYPE) {
+        return Err(Error::InvalidDoHContentType.into());
+    }
+    dns_query(&state, client_addr, &body).await
+}
+
+// Answer a DNS-over-HTTPS request, allowing the response to be cached for the lowest TTL of its
+// records per RFC 8484 section 5.1.
+async fn dns_query(
+    state: &AppState,
+    client_addr: SocketAddr,
+    message: &[u8],
+) -> Result<impl IntoResponse, APIError> {
+    let response = state
+        .doh
+        .answer(message, client_addr)
+        .await
+        .map_err(|err| {
+            tracing::debug!(
+                "rejected DNS-over-HTTPS request from {}: {err}",
+                client_addr.ip()
+            );
+            err
+        })?;
+    let cache_control = match response.max_age {
+        Some(max_age) => format!("max-age={max_age}"),
+        None => "no-cache".to_string(),
+    };
+    Ok((
+        [
+            (header::CONTENT_TYPE, DNS_MESSAGE_CONTENT_TYPE.to_string()),
+            (header::CACHE_CONTROL, cache_control),
+        ],
+        response.message,
+    ))
+}
+
+#[allow(clippy::unused_async)]
+async fn reload(
+    State(state): State<AppState>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+) -> Result<Json<ReloadResult>, APIError> {
+    let client_ip = client_addr.ip();
+    if !is_loopback(client_ip) {
+        return Err(Error::ReloadForbidden(client_ip).into());
+    }
+    tracing::info!("reloading config from {client_ip}");
+    let changes = state.config.reload().map_err(|err| {
+        tracing::error!("config reload failed, keeping current config: {err}");
+        err
+    })?;
+    // NB: the changes themselves are only logged, since they may include config values such as
+    // TSIG key secrets.
+    Ok(Json(ReloadResult {
+        changes: changes.len(),
+    }))
+}
+
+// Returns true if the IP is a loopback address, including IPv4 loopback addresses mapped to IPv6.
+fn is_loopback(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V6(ip) => ip
+            .to_ipv4_mapped()
+            .map_or(ip.is_loopback(), |ip| ip.is_loopback()),
+        IpAddr::V4(ip) => ip.is_loopback(),
+    }
+}
+
+async fn metrics(State(txt_store): State<DynTxtStore>) -> Result<impl IntoResponse, APIError> {
+    let body = metrics::render(&txt_store).await?;
+    Ok(([(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)], body))
+}
+
+// Returns the zone named by the request, or the default zone if the request doesn't name one.
+fn request_zone<'a>(config: &'a Config, zone: Option<&str>) -> Result<&'a Zone, Error> {
+    let Some(name) = zone else {
+        return Ok(config.default_zone());
+    };
+    let domain = LowerName::from(Name::from_str(name)?);
+    config
+        .zone(&domain)
+        .filter(|zone| zone.domain == domain)
+        .ok_or_else(|| Error::UnknownZone(name.to_string()))
+}
+
+// Authorize an update for the given subdomain, returning the zone and fully qualified name to
+// update. When registration is enabled and the request carries account credentials the registered
+// account is used, otherwise the ACL of the zone named by the request is.
+async fn authorize_update<'a>(
+    state: &AppState,
+    config: &'a Config,
+    zone: Option<&str>,
+    headers: &HeaderMap,
+    client_addr: IpAddr,
+    subdomain: &Name,
+) -> Result<(&'a Zone, Name), APIError> {
+    let (zone, fqdn, permitted) =
+        if let Some((username, password)) = credentials(headers).filter(|_| config.registration) {
+            let txt_store = state.txt_store.read().await;
+            match txt_store.get_account(username).await? {
+                Some(account) if account.password_matches(password) => {
+                    let zone = account_zone(config, &account, zone)?;
+                    let fqdn = subdomain.clone().append_domain(&(&zone.domain).into())?;
+                    let permitted = account.update_permitted(client_addr, &(&fqdn).into());
+                    (zone, fqdn, permitted)
+                }
+                _ => {
+                    tracing::debug!("rejected update from {client_addr} for user \"{username}\"");
+                    return Err(Error::Unauthorized(username.to_string()).into());
+                }
+            }
+        } else {
+            let zone = request_zone(config, zone)?;
+            let fqdn = subdomain.clone().append_domain(&(&zone.domain).into())?;
+            let permitted = config.update_permitted(zone, client_addr, subdomain);
+            (zone, fqdn, permitted)
+        };
+
+    if !permitted {
+        tracing::debug!("rejected update from {client_addr} for \"{subdomain}\"",);
+        return Err(Error::AuthForbidden(client_addr, subdomain.into()).into());
+    }
+    Ok((zone, fqdn))
+}
+
+// Returns the zone named by the request, or the zone the account was registered in if the request
+// doesn't name one. NB: acme-dns clients never name a zone.
+fn account_zone<'a>(
+    config: &'a Config,
+    account: &Account,
+    zone: Option<&str>,
+) -> Result<&'a Zone, Error> {
+    if zone.is_some() {
+        return request_zone(config, zone);
+    }
+    config
+        .zone(&account.fqdn)
+        .ok_or_else(|| Error::UnknownZone(account.fqdn.to_string()))
+}
+
+fn credentials(headers: &HeaderMap) -> Option<(&str, &str)> {
+    let header_str = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    Some((header_str("X-Api-User")?, header_str("X-Api-Key")?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    // Loads a config serving the pki.example.com zone and a second lab.example.org zone.
+    fn two_zone_config() -> Config {
+        let zone = |domain: &str| {
+            json!({
+                "domain": domain,
+                "ns_domain": format!("ns1.{domain}"),
+                "ns_admin": format!("dns-admin@{domain}"),
+                "acl": {},
+                "addrs": { domain: [ "93.184.216.34" ] },
+                "ns_records": { domain: [ format!("ns1.{domain}") ] },
+            })
+        };
+        let mut config = zone("pki.example.com");
+        config["zones"] = Value::Array(vec![zone("lab.example.org")]);
+        for (key, value) in [
+            ("api_bind_addr", json!("127.0.0.1:3000")),
+            ("api_timeout", json!(120)),
+            ("dns_udp_bind_addr", json!("127.0.0.1:5353")),
+            ("dns_tcp_bind_addr", json!("127.0.0.1:5353")),
+            ("dns_tcp_timeout", json!(60)),
+            ("registration", json!(true)),
+        ] {
+            config[key] = value;
+        }
+        let path = std::env::temp_dir().join(format!("acmecrab-{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(&path, config.to_string()).unwrap();
+        let loaded = Config::try_from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        loaded.unwrap()
+    }
+
+    #[test]
+    fn account_updates_default_to_account_zone() {
+        let config = two_zone_config();
+        let lab = LowerName::from(Name::from_str("lab.example.org.").unwrap());
+        let (account, _) = Account::generate(&lab, Vec::default()).unwrap();
+
+        let zone = account_zone(&config, &account, None).unwrap();
+        assert_eq!(zone.domain, lab);
+        let subdomain = Name::from(&account.subdomain);
+        let fqdn = subdomain.append_domain(&(&zone.domain).into()).unwrap();
+        assert!(account.update_permitted("127.0.0.1".parse().unwrap(), &fqdn.into()));
+
+        let zone = account_zone(&config, &account, Some("pki.example.com")).unwrap();
+        assert_eq!(zone.domain, config.default_zone().domain);
+        assert!(matches!(
+            account_zone(&config, &account, Some("example.net")),
+            Err(Error::UnknownZone(_))
+        ));
+    }
+
+    #[test]
+    fn reload_requires_loopback() {
+        for ip in ["127.0.0.1", "127.0.0.2", "::1", "::ffff:127.0.0.1"] {
+            assert!(is_loopback(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in ["10.0.0.1", "fd00::2", "::ffff:10.0.0.1", "::"] {
+            assert!(!is_loopback(ip.parse().unwrap()), "{ip}");
+        }
+    }
+}
@@ -1,20 +1,62 @@
 use crate::api::routes;
-use crate::config::Shared;
+use crate::config::DynConfig;
+use crate::dns::{DohHandler, Notifier};
 use crate::txt_store::DynTxtStore;
+use axum::extract::FromRef;
 use std::future::Future;
 use std::net::SocketAddr;
 
 #[derive(Clone)]
 pub(super) struct AppState {
-    pub config: Shared,
+    pub config: DynConfig,
     pub txt_store: DynTxtStore,
+    pub notifier: Notifier,
+    pub doh: DohHandler,
 }
 
-/// Construct a [`Future`] for a new API server with the given [Shared] [Config][`crate::config::Config`].
-/// Its update API will mutate TXT records in the [`DynTxtStore`]
-pub fn new(config: Shared, txt_store: DynTxtStore) -> impl Future<Output = hyper::Result<()>> {
-    axum::Server::bind(&config.api_bind_addr).serve(
-        routes::new(AppState { config, txt_store })
+impl FromRef<AppState> for DynTxtStore {
+    fn from_ref(state: &AppState) -> Self {
+        state.txt_store.clone()
+    }
+}
+
+/// Construct a [`Future`] for a new API server with the given [`DynConfig`].
+/// Its update API will mutate TXT records in the [`DynTxtStore`], and tell the [`Notifier`] the
+/// zone changed. DNS-over-HTTPS requests are answered by the [`DohHandler`].
+///
+/// When the `shutdown` future completes the server stops accepting new connections, and the
+/// returned future completes once in-flight requests have finished.
+pub fn new(
+    config: DynConfig,
+    txt_store: DynTxtStore,
+    notifier: Notifier,
+    doh: DohHandler,
+    shutdown: impl Future<Output = ()>,
+) -> impl Future<Output = hyper::Result<()>> {
+    axum::Server::bind(&config.current().api_bind_addr)
+        .serve(
+            routes::new(AppState {
+                config,
+                txt_store,
+                notifier,
+                doh,
+            })
             .into_make_service_with_connect_info::<SocketAddr>(),
-    )
+        )
+        .with_graceful_shutdown(shutdown)
+}
+
+/// Construct a [`Future`] for a new metrics server serving only the
+/// [`/metrics` endpoint][crate::api#metrics-get] on the given bind address.
+///
+/// When the `shutdown` future completes the server stops accepting new connections, and the
+/// returned future completes once in-flight requests have finished.
+pub fn new_metrics(
+    bind_addr: SocketAddr,
+    txt_store: DynTxtStore,
+    shutdown: impl Future<Output = ()>,
+) -> impl Future<Output = hyper::Result<()>> {
+    axum::Server::bind(&bind_addr)
+        .serve(routes::new_metrics(txt_store).into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(shutdown)
 }
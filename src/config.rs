@@ -3,43 +3,59 @@
 use crate::error::Error;
 use crate::txt_store::DynTxtStore;
 use crate::{FileTxtStore, InMemoryTxtStore};
+use base64::engine::general_purpose;
+use base64::Engine;
 use ipnetwork::IpNetwork;
 use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use serde_with::{serde_as, DurationSeconds};
+use sha2::{Digest, Sha256};
 use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::BufReader;
 use std::net::{IpAddr, SocketAddr};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock as StdRwLock};
 use std::time::Duration;
 use tokio::sync::RwLock;
-use trust_dns_server::client::rr::{LowerName, Name};
+use trust_dns_proto::error::ProtoError;
+use trust_dns_proto::rr::rdata::caa;
+use trust_dns_proto::rr::rdata::CAA;
+use trust_dns_server::client::rr::{LowerName, Name, RecordType};
 
 /// Shared is a type alias for a reference counted [Config].
 pub type Shared = Arc<Config>;
 
+/// `DynConfig` holds the current [Shared] config along with the path it was loaded from, allowing
+/// the config to be [reloaded][DynConfig::reload] and atomically replaced at runtime. Clones
+/// share the same underlying config.
+#[derive(Clone, Debug)]
+pub struct DynConfig {
+    path: PathBuf,
+    current: Arc<StdRwLock<Shared>>,
+}
+
 #[serde_as]
 #[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
 /// Config describes the ACME Crab runtime configuration. All values are required unless otherwise
 /// specified.
 pub struct Config {
-    /// Fully qualified domain name of the ACME crab instance.
+    /// The [`Zone`]s served by the [DNS server][crate::dns], each with its own SOA settings,
+    /// ACL and static records. Queries are answered from the zone with the longest
+    /// [`Zone::domain`] the query name is within. At least one zone is required.
     ///
-    /// The [HTTP API][crate::api] allows setting dynamic TXT records for **subdomains** of this
-    /// configured domain.
-    pub domain: LowerName,
-
-    /// Fully qualified domain name of the nameserver responsible for the [Config::domain].
-    /// This value is used in the [SOA record][crate::dns#soa] for the [Config::domain].
-    pub ns_domain: LowerName,
+    /// The keys of a single [`Zone`] may also be given at the top level of the config file,
+    /// instead of in `zones`. They are loaded as the first zone.
+    #[serde(default)]
+    pub zones: Vec<Zone>,
 
-    /// Email address of the nameserver administrator for the [Config::domain].
-    /// This value is used in the [SOA record][crate::dns#soa] for the [Config::domain].
-    pub ns_admin: String,
+    /// Optional scheme used for the serial of the [SOA record][crate::dns#soa]. Defaults to
+    /// [`SoaSerial::Counter`].
+    #[serde(default)]
+    pub soa_serial: SoaSerial,
 
     /// Optional path to a JSON state file to be used to persist dynamic TXT records between
     /// restarts. If omitted, an in-memory store will be used and TXT records set with the API
@@ -47,6 +63,19 @@ pub struct Config {
     /// If provided, and the file exists, it will be loaded to populate the initial TXT records.
     pub txt_store_state_path: Option<String>,
 
+    /// Optional kind of TXT store used to hold dynamic TXT records and registered accounts. If
+    /// omitted, a [`TxtStoreKind::File`] store is used when [`Config::txt_store_state_path`] is
+    /// set, and a [`TxtStoreKind::Memory`] store otherwise.
+    #[serde(default)]
+    pub txt_store_kind: Option<TxtStoreKind>,
+
+    /// Optional maximum age (expressed in seconds) of dynamic TXT record values. Values older
+    /// than this are no longer served and are periodically removed from the TXT store. Must be at
+    /// least 1 second. If omitted, values are served until they are replaced by newer updates.
+    #[serde_as(as = "Option<DurationSeconds<u64>>")]
+    #[serde(default)]
+    pub txt_max_age: Option<Duration>,
+
     /// Bind address for the [HTTP API][crate::api]. This address must be a loopback address,
     /// or an address within a private network. It must specify both an address and a port.
     pub api_bind_addr: SocketAddr,
@@ -55,6 +84,12 @@ pub struct Config {
     #[serde_as(as = "DurationSeconds<u64>")]
     pub api_timeout: Duration,
 
+    /// Optional bind address for serving the [`/metrics` endpoint][crate::api#metrics-get]
+    /// separately from the [HTTP API][crate::api]. If omitted, metrics are served by the HTTP
+    /// API. Must specify both an address and a port.
+    #[serde(default)]
+    pub metrics_bind_addr: Option<SocketAddr>,
+
     /// UDP bind address for responding to [DNS][crate::dns] requests.. Must specify both an
     /// address and a port.
     pub dns_udp_bind_addr: SocketAddr,
@@ -67,11 +102,120 @@ pub struct Config {
     #[serde_as(as = "DurationSeconds<u64>")]
     pub dns_tcp_timeout: Duration,
 
-    /// A mapping between [`IpNetwork`]s to a [`HashSet`] of [`LowerName`] subdomains. Clients in the
-    /// `IpNetwork` key may `POST` updates for the subdomains in the associated set using the
-    /// [HTTP API][crate::api] that will be served when
+    /// Optional TCP bind address for responding to [DNS-over-TLS][crate::dns#dns-over-tls]
+    /// requests, e.g. port 853. Requires [`Config::dns_tls_cert_path`] and
+    /// [`Config::dns_tls_key_path`], and ACME Crab to be built with the `dns-over-tls` feature.
+    /// If omitted, DNS-over-TLS isn't served.
+    #[serde(default)]
+    pub dns_tls_bind_addr: Option<SocketAddr>,
+
+    /// Optional path to the PEM encoded certificate chain served for
+    /// [DNS-over-TLS][crate::dns#dns-over-tls]. The file is reloaded when it changes.
+    #[serde(default)]
+    pub dns_tls_cert_path: Option<String>,
+
+    /// Optional path to the PEM encoded private key of the [`Config::dns_tls_cert_path`]
+    /// certificate. The file is reloaded when it changes.
+    #[serde(default)]
+    pub dns_tls_key_path: Option<String>,
+
+    /// Optional maximum size (in bytes) of [DNS][crate::dns] responses sent over UDP to clients
+    /// using EDNS(0). Clients advertising a smaller payload size get smaller responses, and
+    /// responses that don't fit are truncated. Must be at least 512. Defaults to 1232.
+    #[serde(default = "default_dns_max_udp_payload")]
+    pub dns_max_udp_payload: u16,
+
+    /// Optional [response rate limiting][crate::dns#response-rate-limiting] of
+    /// [DNS][crate::dns] responses sent over UDP, to prevent ACME Crab being used to reflect and
+    /// amplify traffic. If omitted, responses aren't rate limited.
+    #[serde(default)]
+    pub rate_limit: Option<RateLimit>,
+
+    /// Optional [DNSSEC][crate::dns#dnssec] signing keys. When set, responses to queries with the
+    /// DNSSEC OK bit are signed online, and DNSKEY queries for each [`Zone::domain`] are
+    /// answered. Requires ACME Crab to be built with the `dnssec` feature. If omitted, responses
+    /// aren't signed.
+    #[serde(default)]
+    pub dnssec: Option<Dnssec>,
+
+    /// Optional deadline (expressed in seconds) for draining in-flight [HTTP API][crate::api]
+    /// and [DNS][crate::dns] requests at shutdown. Defaults to 10 seconds.
+    #[serde_as(as = "DurationSeconds<u64>")]
+    #[serde(default = "default_shutdown_timeout")]
+    pub shutdown_timeout: Duration,
+
+    /// Optional path to a [WireGuard] interface config file (e.g. `/etc/wireguard/wg0.conf`), or
+    /// a file with the output of `wg show <interface> dump`, that the allowed IPs of
+    /// [`AclKey::WireGuardPeer`]s are read from. The file is re-read whenever the config is
+    /// loaded. Required if any [`Zone::acl`] has peer public key entries.
+    ///
+    /// [WireGuard]: https://www.wireguard.com
+    #[serde(default)]
+    pub wireguard_peers_path: Option<String>,
+
+    /// Optional flag enabling the [`/register` API endpoint][crate::api#register-post]. When
+    /// enabled, `POST`s to the [`/update` API endpoint][crate::api#update-post] that carry
+    /// `X-Api-User` and `X-Api-Key` headers are authorized against registered
+    /// [`Account`][crate::account::Account]s instead of the [`Zone::acl`]s. Defaults to false.
+    #[serde(default)]
+    pub registration: bool,
+
+    /// Optional list of [`IpNetwork`]s allowed to request [zone transfers][crate::dns#axfr] of each
+    /// [`Zone::domain`] zone, e.g. secondary nameservers. If omitted, zone transfers are refused.
+    #[serde(default)]
+    pub transfer_allowed: Vec<IpNetwork>,
+
+    /// Optional list of secondary nameserver [`SocketAddr`]s sent a [DNS NOTIFY][crate::dns#notify]
+    /// whenever the dynamic TXT records change, prompting them to refresh the zone. If omitted, no
+    /// NOTIFY messages are sent.
+    #[serde(default)]
+    pub notify_addrs: Vec<SocketAddr>,
+
+    // The allowed IPs of each WireGuard peer in the wireguard_peers_path file, by public key.
+    // Read when the config is loaded.
+    #[serde(skip)]
+    wireguard_allowed_ips: HashMap<String, Vec<IpNetwork>>,
+}
+
+/// A zone served by the [DNS server][crate::dns], with the subdomains that may be updated using
+/// the [HTTP API][crate::api]. See [`Config::zones`].
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+pub struct Zone {
+    /// Fully qualified domain name of the zone apex.
+    ///
+    /// The [HTTP API][crate::api] allows setting dynamic TXT records for **subdomains** of this
+    /// configured domain.
+    pub domain: LowerName,
+
+    /// Fully qualified domain name of the nameserver responsible for the [`Zone::domain`].
+    /// This value is used in the [SOA record][crate::dns#soa] for the [`Zone::domain`].
+    pub ns_domain: LowerName,
+
+    /// Email address of the nameserver administrator for the [`Zone::domain`].
+    /// This value is used in the [SOA record][crate::dns#soa] for the [`Zone::domain`].
+    pub ns_admin: String,
+
+    /// Optional refresh, retry, expire and minimum timers for the [SOA record][crate::dns#soa].
+    /// Each omitted timer uses its [`SoaTimers`] default.
+    #[serde(default)]
+    pub soa_timers: SoaTimers,
+
+    /// Optional TTLs for the records served by the [DNS server][crate::dns], by record type.
+    /// Each omitted TTL uses its [`RecordTtls`] default.
+    #[serde(default)]
+    pub ttls: RecordTtls,
+
+    /// A mapping between [`AclKey`]s to a [`HashSet`] of [`SubdomainPattern`]s. Clients in the
+    /// networks of the `AclKey` may `POST` updates for the subdomains matching a pattern in the
+    /// associated set using the [HTTP API][crate::api] that will be served when
     /// [TXT records are queried][crate::dns#dynamic-txt-records].
-    pub acl: HashMap<IpNetwork, HashSet<LowerName>>,
+    pub acl: HashMap<AclKey, HashSet<SubdomainPattern>>,
+
+    /// Optional mapping between TSIG key names and the [`TsigKey`] used to authenticate
+    /// [RFC-2136 UPDATE][crate::dns#update] messages signed with that key. Each key may only add
+    /// and delete TXT records for its own subdomains. If omitted, UPDATE messages are refused.
+    #[serde(default)]
+    pub tsig_keys: HashMap<LowerName, TsigKey>,
 
     /// A mapping between fully qualified [`LowerName`]s to a [`Vec`] of [`IpAddr`] values that
     /// should be served when [A/AAAA records are queried][crate::dns#aaaaa] for the keyed
@@ -82,8 +226,576 @@ pub struct Config {
     /// [`LowerName`] values that should be served when [NS records are queried][crate::dns#ns] for
     /// the keyed [`LowerName`].
     pub ns_records: HashMap<LowerName, Vec<LowerName>>,
+
+    /// Optional mapping between fully qualified [`LowerName`] aliases within the [`Zone::domain`]
+    /// zone and the fully qualified [`LowerName`] they are an alias of. CNAME records are
+    /// [served][crate::dns#cname] for the aliases, and in-zone targets are chased. An alias can't
+    /// also be the [`Zone::domain`], or have [`Zone::addrs`], [`Zone::ns_records`] or ACL
+    /// TXT records.
+    #[serde(default)]
+    pub cnames: HashMap<LowerName, LowerName>,
+
+    /// Optional mapping between fully qualified [`LowerName`]s and a [`Vec`] of [`CaaRecord`]s
+    /// that should be served when [CAA records are queried][crate::dns#caa] for the keyed
+    /// [`LowerName`]. Each name must be the [`Zone::domain`] or a name in [`Zone::addrs`].
+    #[serde(default)]
+    pub caa_records: HashMap<LowerName, Vec<CaaRecord>>,
+
+    // The CAA record data for each name in caa_records. Derived and validated when the config is
+    // loaded.
+    #[serde(skip)]
+    caa_rdata: HashMap<LowerName, Vec<CAA>>,
+
+    // The fully qualified names of every exact subdomain in the ACL. Derived from the ACL when the
+    // config is loaded, and used to quickly determine whether to return NXDOMAIN for a TXT lookup.
+    #[serde(skip)]
+    txt_domain_set: HashSet<LowerName>,
+
+    // The ACL subdomain patterns that aren't exact names, checked for TXT lookups that aren't in
+    // the txt_domain_set, and the fully qualified suffix of each.
+    #[serde(skip)]
+    txt_domain_patterns: Vec<SubdomainPattern>,
+    #[serde(skip)]
+    txt_domain_suffixes: HashSet<LowerName>,
+}
+
+/// A [CAA record][RFC-8659] property restricting which certificate authorities may issue
+/// certificates for a name.
+///
+/// [RFC-8659]: https://www.rfc-editor.org/rfc/rfc8659
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+pub struct CaaRecord {
+    /// Optional issuer critical flag. Defaults to false.
+    #[serde(default)]
+    pub critical: bool,
+
+    /// The property tag.
+    pub tag: CaaTag,
+
+    /// The property value. For [`CaaTag::Issue`] and [`CaaTag::IssueWild`] this is an issuer
+    /// domain name, optionally followed by `; key=value` parameters, or `;` to forbid issuance.
+    /// For [`CaaTag::Iodef`] this is a `mailto:` or `https:` URL.
+    pub value: String,
+}
+
+/// The timers (expressed in seconds) of the [SOA record][crate::dns#soa]. The defaults follow
+/// [RIPE-203][RIPE-203]. Timers must be at most 2^31 - 1 seconds, the retry timer can't exceed
+/// the refresh timer, and the expire timer must be at least the sum of the two.
+///
+/// [RIPE-203]: https://www.ripe.net/publications/docs/ripe-203
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Eq, PartialEq)]
+pub struct SoaTimers {
+    /// How often secondaries refresh the zone. Defaults to 86400 (24 hours).
+    #[serde(default = "default_soa_refresh")]
+    pub refresh: u32,
+
+    /// How long secondaries wait before retrying a failed refresh. Defaults to 7200 (2 hours).
+    #[serde(default = "default_soa_retry")]
+    pub retry: u32,
+
+    /// How long secondaries keep serving the zone without a successful refresh. Defaults to
+    /// 3600000 (1000 hours).
+    #[serde(default = "default_soa_expire")]
+    pub expire: u32,
+
+    /// Upper bound on how long resolvers cache negative answers. Defaults to 172800 (2 days).
+    #[serde(default = "default_soa_minimum")]
+    pub minimum: u32,
+}
+
+impl Default for SoaTimers {
+    fn default() -> Self {
+        Self {
+            refresh: default_soa_refresh(),
+            retry: default_soa_retry(),
+            expire: default_soa_expire(),
+            minimum: default_soa_minimum(),
+        }
+    }
+}
+
+/// The TTLs (expressed in seconds) of the records served by the [DNS server][crate::dns]. TTLs
+/// must be at most 2^31 - 1 seconds.
+///
+/// Negative answers are cached for the lesser of the `soa` TTL and [`SoaTimers::minimum`], so
+/// raising the `soa` TTL also lengthens how long resolvers remember that a TXT record doesn't
+/// exist yet.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Eq, PartialEq)]
+pub struct RecordTtls {
+    /// TTL of dynamic TXT records. Kept short so resolvers see new challenge responses quickly.
+    /// Defaults to 1.
+    #[serde(default = "default_txt_ttl")]
+    pub txt: u32,
+
+    /// TTL of the static A, AAAA, CNAME and CAA records from the config. Defaults to 3600.
+    #[serde(rename = "static", default = "default_static_ttl")]
+    pub static_records: u32,
+
+    /// TTL of NS records. Defaults to 3600.
+    #[serde(default = "default_ns_ttl")]
+    pub ns: u32,
+
+    /// TTL of the SOA record. Defaults to 1.
+    #[serde(default = "default_soa_ttl")]
+    pub soa: u32,
+}
+
+impl Default for RecordTtls {
+    fn default() -> Self {
+        Self {
+            txt: default_txt_ttl(),
+            static_records: default_static_ttl(),
+            ns: default_ns_ttl(),
+            soa: default_soa_ttl(),
+        }
+    }
+}
+
+impl RecordTtls {
+    /// Returns the TTL for records of the given type.
+    #[must_use]
+    pub fn ttl(&self, record_type: RecordType) -> u32 {
+        match record_type {
+            RecordType::TXT => self.txt,
+            RecordType::NS => self.ns,
+            RecordType::SOA => self.soa,
+            _ => self.static_records,
+        }
+    }
+}
+
+/// The limits applied by [response rate limiting][crate::dns#response-rate-limiting]. Responses
+/// are counted per client network, separately for each kind of response. A rate of 0 disables
+/// limiting for that kind of response.
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+pub struct RateLimit {
+    /// Responses with records, or without records for names that exist (NODATA), allowed per
+    /// second for each client network. Defaults to 10.
+    #[serde(default = "default_responses_per_second")]
+    pub responses_per_second: u32,
+
+    /// NXDOMAIN responses allowed per second for each client network. Defaults to 5.
+    #[serde(default = "default_nxdomains_per_second")]
+    pub nxdomains_per_second: u32,
+
+    /// Error responses (e.g. REFUSED or NOTIMP) allowed per second for each client network.
+    /// Defaults to 5.
+    #[serde(default = "default_errors_per_second")]
+    pub errors_per_second: u32,
+
+    /// How often a rate limited response is sent truncated, instead of being dropped, so that
+    /// legitimate clients sharing the network retry over TCP. E.g. 2 sends every second rate
+    /// limited response truncated. 0 drops every rate limited response. Defaults to 2.
+    #[serde(default = "default_slip")]
+    pub slip: u32,
+
+    /// Prefix length of the networks IPv4 clients are grouped by. Defaults to 24.
+    #[serde(default = "default_ipv4_prefix_len")]
+    pub ipv4_prefix_len: u8,
+
+    /// Prefix length of the networks IPv6 clients are grouped by. Defaults to 56.
+    #[serde(default = "default_ipv6_prefix_len")]
+    pub ipv6_prefix_len: u8,
+
+    /// Optional list of [`IpNetwork`]s whose responses are never rate limited, e.g. secondary
+    /// nameservers or monitoring.
+    #[serde(default)]
+    pub exempt: Vec<IpNetwork>,
+}
+
+impl RateLimit {
+    /// Checks if responses to the given [`IpAddr`] are exempt from rate limiting.
+    #[must_use]
+    pub fn is_exempt(&self, client_ip: IpAddr) -> bool {
+        self.exempt
+            .iter()
+            .any(|network| network.contains(client_ip))
+    }
+
+    /// Returns the network the given [`IpAddr`] is grouped by for rate limiting.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidConfig`] if the prefix length for the address family is too long.
+    pub fn client_network(&self, client_ip: IpAddr) -> Result<IpNetwork, Error> {
+        let prefix_len = match client_ip {
+            IpAddr::V4(_) => self.ipv4_prefix_len,
+            IpAddr::V6(_) => self.ipv6_prefix_len,
+        };
+        IpNetwork::new(client_ip, prefix_len)
+            .and_then(|network| IpNetwork::new(network.network(), prefix_len))
+            .map_err(|err| Error::InvalidConfig(format!("invalid rate limit prefix length: {err}")))
+    }
+}
+
+/// The [DNSSEC][crate::dns#dnssec] keys used to sign every [`Zone::domain`] zone. The key
+/// signing key (KSK) signs the DNSKEY records, and the zone signing key (ZSK) signs all other
+/// records. Both keys must use the same algorithm.
+#[serde_as]
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+pub struct Dnssec {
+    /// The signing algorithm of both keys.
+    pub algorithm: DnssecAlgorithm,
+
+    /// Path to the PKCS#8 private key of the KSK, PEM or DER encoded. The DS record for the
+    /// parent zone is derived from this key.
+    pub ksk_path: String,
+
+    /// Path to the PKCS#8 private key of the ZSK, PEM or DER encoded.
+    pub zsk_path: String,
+
+    /// Optional validity period (expressed in seconds) of RRSIG records. Signatures are created
+    /// when responses are sent, so this only needs to cover how long resolvers may cache them.
+    /// Must be at least 3600. Defaults to 7 days.
+    #[serde_as(as = "DurationSeconds<u64>")]
+    #[serde(default = "default_dnssec_signature_validity")]
+    pub signature_validity: Duration,
+}
+
+/// The [`Dnssec`] signing algorithms that can be configured.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DnssecAlgorithm {
+    /// ECDSA with curve P-256 and SHA-256 (algorithm 13). The recommended algorithm.
+    Ecdsap256sha256,
+
+    /// ECDSA with curve P-384 and SHA-384 (algorithm 14).
+    Ecdsap384sha384,
+
+    /// Ed25519 (algorithm 15).
+    Ed25519,
+}
+
+/// A key of the [`Zone::acl`], identifying the clients that may update the subdomains of the
+/// entry by source IP. Either a CIDR network, e.g. `10.0.0.0/24`, or the base64 public key of a
+/// [WireGuard] peer, which stands for the allowed IPs of the peer so that the ACL follows the
+/// [cryptokey routing] of the tunnel.
+///
+/// [WireGuard]: https://www.wireguard.com
+/// [cryptokey routing]: https://www.wireguard.com/#cryptokey-routing
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq, Hash)]
+#[serde(try_from = "String", into = "String")]
+pub enum AclKey {
+    /// Clients with a source IP in the network.
+    Network(IpNetwork),
+
+    /// Clients with a source IP in the allowed IPs of the [WireGuard] peer with this public key,
+    /// as read from the [`Config::wireguard_peers_path`].
+    ///
+    /// [WireGuard]: https://www.wireguard.com
+    WireGuardPeer(String),
+}
+
+impl TryFrom<String> for AclKey {
+    type Error = Error;
+
+    fn try_from(key: String) -> Result<Self, Self::Error> {
+        if let Ok(network) = IpNetwork::from_str(&key) {
+            return Ok(Self::Network(network));
+        }
+        match general_purpose::STANDARD.decode(&key) {
+            Ok(public_key) if public_key.len() == 32 => Ok(Self::WireGuardPeer(key)),
+            _ => Err(Error::InvalidConfig(format!(
+                "invalid ACL key \"{key}\": not a CIDR network or WireGuard public key"
+            ))),
+        }
+    }
+}
+
+impl From<AclKey> for String {
+    fn from(key: AclKey) -> Self {
+        match key {
+            AclKey::Network(network) => network.to_string(),
+            AclKey::WireGuardPeer(public_key) => public_key,
+        }
+    }
+}
+
+/// A subdomain in the [`Zone::acl`], relative to the [`Zone::domain`]. Each label is matched
+/// against the label in the same position of a subdomain:
+///
+/// * `*` matches any single label, e.g. `*.web` matches `a.web` but not `a.b.web` or `web`.
+/// * `{n}` matches one or more digits within a label, e.g. `host-{n}` matches `host-1` and
+///   `host-42`.
+/// * `**` as the first label matches one or more labels, delegating everything below the rest of
+///   the pattern, e.g. `**.team-a` matches `a.team-a` and `a.b.team-a` but not `team-a`.
+///
+/// Other labels match exactly, ignoring case. A pattern without any of these is an exact name.
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq, Hash)]
+#[serde(try_from = "String", into = "String")]
+pub struct SubdomainPattern {
+    pattern: String,
+    labels: Vec<LabelPattern>,
+}
+
+// How a label of a SubdomainPattern matches a label of a subdomain.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+enum LabelPattern {
+    Exact(Vec<u8>),
+    Any,
+    Numbered(Vec<u8>, Vec<u8>),
+    AnyDepth,
+}
+
+impl SubdomainPattern {
+    /// Returns the pattern as a [`Name`] if it only matches a single subdomain.
+    #[must_use]
+    pub fn exact_name(&self) -> Option<Name> {
+        self.labels
+            .iter()
+            .all(|label| matches!(label, LabelPattern::Exact(_)))
+            .then(|| Name::from_str(&self.pattern).ok())
+            .flatten()
+    }
+
+    /// Returns the name every subdomain matching the pattern is below (or is), made of the labels
+    /// after the last label that isn't matched exactly. The empty [`Name`] is returned if the last
+    /// label isn't matched exactly.
+    #[must_use]
+    pub fn suffix(&self) -> Name {
+        let exact = self
+            .labels
+            .iter()
+            .rev()
+            .take_while(|label| matches!(label, LabelPattern::Exact(_)))
+            .count();
+        let labels = self.pattern.split('.').skip(self.labels.len() - exact);
+        Name::from_labels(labels.map(str::as_bytes)).unwrap_or_default()
+    }
+
+    /// Checks if the pattern matches the given relative subdomain [`Name`].
+    #[must_use]
+    pub fn matches(&self, subdomain: &Name) -> bool {
+        let labels: Vec<Vec<u8>> = subdomain.iter().map(<[u8]>::to_ascii_lowercase).collect();
+        let labels: Vec<&[u8]> = labels.iter().map(Vec::as_slice).collect();
+        self.matches_labels(&labels)
+    }
+
+    // Match the labels of a relative subdomain, which must be lowercase.
+    fn matches_labels(&self, labels: &[&[u8]]) -> bool {
+        let (depth, patterns) = match self.labels.split_first() {
+            Some((LabelPattern::AnyDepth, rest)) => (labels.len() > rest.len(), rest),
+            _ => (labels.len() == self.labels.len(), self.labels.as_slice()),
+        };
+        depth
+            && patterns.iter().rev().zip(labels.iter().rev()).all(
+                |(pattern, label)| match pattern {
+                    LabelPattern::Exact(exact) => exact == label,
+                    LabelPattern::Any | LabelPattern::AnyDepth => true,
+                    LabelPattern::Numbered(prefix, suffix) => {
+                        label.len() > prefix.len() + suffix.len()
+                            && label.starts_with(prefix)
+                            && label.ends_with(suffix)
+                            && label[prefix.len()..label.len() - suffix.len()]
+                                .iter()
+                                .all(u8::is_ascii_digit)
+                    }
+                },
+            )
+    }
+}
+
+impl TryFrom<String> for SubdomainPattern {
+    type Error = Error;
+
+    fn try_from(pattern: String) -> Result<Self, Self::Error> {
+        let pattern = pattern.trim_end_matches('.').to_ascii_lowercase();
+        let invalid = |reason: &str| {
+            Error::InvalidConfig(format!("invalid ACL subdomain \"{pattern}\": {reason}"))
+        };
+        if pattern.is_empty() {
+            return Err(invalid("empty subdomain"));
+        }
+        let mut labels = Vec::default();
+        for (i, label) in pattern.split('.').enumerate() {
+            labels.push(match label {
+                "**" if i == 0 => LabelPattern::AnyDepth,
+                "**" => return Err(invalid("** must be the first label")),
+                "*" => LabelPattern::Any,
+                _ if label.matches("{n}").count() > 1 => {
+                    return Err(invalid("{n} can only be used once per label"))
+                }
+                _ => {
+                    // NB: a placeholder is validated as a digit, so labels are valid DNS labels.
+                    Name::from_str(&label.replacen("{n}", "0", 1))
+                        .ok()
+                        .filter(|name| name.num_labels() == 1)
+                        .ok_or_else(|| invalid("not a valid DNS label"))?;
+                    match label.split_once("{n}") {
+                        Some((prefix, suffix)) => LabelPattern::Numbered(
+                            prefix.as_bytes().to_vec(),
+                            suffix.as_bytes().to_vec(),
+                        ),
+                        None => LabelPattern::Exact(label.as_bytes().to_vec()),
+                    }
+                }
+            });
+        }
+        Ok(Self { pattern, labels })
+    }
+}
+
+impl From<SubdomainPattern> for String {
+    fn from(pattern: SubdomainPattern) -> Self {
+        pattern.pattern
+    }
+}
+
+/// A [TSIG][RFC-8945] shared secret key, authorizing [RFC-2136 UPDATE][crate::dns#update]
+/// messages to add and delete TXT records for a set of subdomains.
+///
+/// [RFC-8945]: https://www.rfc-editor.org/rfc/rfc8945
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+pub struct TsigKey {
+    /// The HMAC algorithm of the key.
+    pub algorithm: TsigAlgorithm,
+
+    /// The base64 encoded secret of the key, e.g. as generated by `tsig-keygen`. Only a
+    /// fingerprint of the secret is included when logging config changes.
+    #[serde(serialize_with = "serialize_secret")]
+    pub secret: String,
+
+    /// Subdomains of the [`Zone::domain`] that UPDATE messages signed with the key may add and
+    /// delete TXT records for. These are served like the subdomains in the [`Zone::acl`].
+    pub subdomains: HashSet<LowerName>,
+}
+
+impl TsigKey {
+    /// Returns the decoded secret of the key.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidConfig`] if the secret isn't valid base64.
+    pub fn secret_bytes(&self) -> Result<Vec<u8>, Error> {
+        general_purpose::STANDARD
+            .decode(&self.secret)
+            .map_err(|err| Error::InvalidConfig(format!("invalid TSIG key secret: {err}")))
+    }
+}
+
+/// The [`TsigKey`] HMAC algorithms that can be configured.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TsigAlgorithm {
+    /// HMAC-SHA224.
+    HmacSha224,
+
+    /// HMAC-SHA256. The default algorithm of most TSIG tooling.
+    HmacSha256,
+
+    /// HMAC-SHA384.
+    HmacSha384,
+
+    /// HMAC-SHA512.
+    HmacSha512,
+}
+
+// Serializes a TSIG key secret as a short fingerprint, so that config changes can be logged
+// without revealing the secret.
+fn serialize_secret<S: serde::Serializer>(secret: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    let digest = Sha256::digest(secret.as_bytes());
+    let fingerprint = u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]);
+    serializer.serialize_str(&format!("sha256:{fingerprint:08x}"))
+}
+
+/// The [`CaaRecord`] property tags that can be configured.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CaaTag {
+    /// Authorizes an issuer to issue certificates for the name.
+    Issue,
+
+    /// Authorizes an issuer to issue wildcard certificates for the name.
+    IssueWild,
+
+    /// A URL for reporting issuance requests that violate the CAA policy.
+    Iodef,
+}
+
+impl CaaRecord {
+    fn to_rdata(&self) -> Result<CAA, ProtoError> {
+        let value = self.value.as_bytes();
+        Ok(match self.tag {
+            CaaTag::Issue => {
+                let (name, options) = caa::read_issuer(value)?;
+                CAA::new_issue(self.critical, name, options)
+            }
+            CaaTag::IssueWild => {
+                let (name, options) = caa::read_issuer(value)?;
+                CAA::new_issuewild(self.critical, name, options)
+            }
+            CaaTag::Iodef => CAA::new_iodef(self.critical, caa::read_iodef(value)?),
+        })
+    }
+}
+
+/// The kinds of [`TxtStore`][crate::txt_store::TxtStore] that can be selected with
+/// [`Config::txt_store_kind`].
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TxtStoreKind {
+    /// An [`InMemoryTxtStore`]. State is lost between restarts.
+    Memory,
+
+    /// A [`FileTxtStore`] persisting JSON state to the [`Config::txt_store_state_path`].
+    File,
+
+    /// An SQLite-backed store at the [`Config::txt_store_state_path`]. Requires ACME Crab to be
+    /// built with the `sqlite` cargo feature.
+    Sqlite,
+}
+
+/// The schemes for the serial of the [SOA record][crate::dns#soa] that can be selected with
+/// [`Config::soa_serial`].
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SoaSerial {
+    /// A counter persisted by the [`TxtStore`][crate::txt_store::TxtStore], incremented each time
+    /// the store changes. It never falls behind the current date (`YYYYMMDD`), so switching from
+    /// [`SoaSerial::Date`] never lowers the serial.
+    #[default]
+    Counter,
+
+    /// The current date (`YYYYMMDD`). Only changes once a day, regardless of store changes.
+    Date,
 }
 
+// The largest TTL or SOA timer allowed by RFC 2181 section 8.
+const MAX_TTL: u32 = 2_147_483_647;
+
+// Top-level config keys that are only read at startup. Changing these requires a restart.
+const RESTART_REQUIRED_KEYS: &[&str] = &[
+    "txt_store_state_path",
+    "txt_store_kind",
+    "txt_max_age",
+    "api_bind_addr",
+    "api_timeout",
+    "metrics_bind_addr",
+    "dns_udp_bind_addr",
+    "dns_tcp_bind_addr",
+    "dns_tcp_timeout",
+    "dns_tls_bind_addr",
+    "dns_tls_cert_path",
+    "dns_tls_key_path",
+    "dnssec",
+    "shutdown_timeout",
+];
+
+// Keys of a Zone, which may be given at the top level of the config file for the first zone.
+const ZONE_KEYS: &[&str] = &[
+    "domain",
+    "ns_domain",
+    "ns_admin",
+    "soa_timers",
+    "ttls",
+    "acl",
+    "tsig_keys",
+    "addrs",
+    "ns_records",
+    "cnames",
+    "caa_records",
+];
+
 lazy_static! {
     // NOTE(XXX): Once the "ip" feature has stabilized we can use Ipv6Addr.is_unique_local[0].
     //            Presently this feature is unstable so we home-roll. See also RFC 4193[1].
@@ -92,6 +804,74 @@ lazy_static! {
     static ref IPV6_UNIQUE_LOCAL_NETWORK: IpNetwork = IpNetwork::from_str("fc00::/7").unwrap();
 }
 
+fn default_dns_max_udp_payload() -> u16 {
+    1232
+}
+
+fn default_responses_per_second() -> u32 {
+    10
+}
+
+fn default_nxdomains_per_second() -> u32 {
+    5
+}
+
+fn default_errors_per_second() -> u32 {
+    5
+}
+
+fn default_slip() -> u32 {
+    2
+}
+
+fn default_ipv4_prefix_len() -> u8 {
+    24
+}
+
+fn default_ipv6_prefix_len() -> u8 {
+    56
+}
+
+fn default_dnssec_signature_validity() -> Duration {
+    Duration::from_secs(7 * 24 * 3600)
+}
+
+fn default_shutdown_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_soa_refresh() -> u32 {
+    86_400
+}
+
+fn default_soa_retry() -> u32 {
+    7_200
+}
+
+fn default_soa_expire() -> u32 {
+    3_600_000
+}
+
+fn default_soa_minimum() -> u32 {
+    172_800
+}
+
+fn default_txt_ttl() -> u32 {
+    1
+}
+
+fn default_static_ttl() -> u32 {
+    3_600
+}
+
+fn default_ns_ttl() -> u32 {
+    3_600
+}
+
+fn default_soa_ttl() -> u32 {
+    1
+}
+
 impl Config {
     /// Try to load a [Config] from the provided path.
     ///
@@ -103,70 +883,330 @@ impl Config {
     ///
     /// Returns [`Error::InsecureAPIBind`] if the API bind address in the config is not
     /// a loopback address, or an IP in a private IP range.
+    ///
+    /// Returns [`Error::IO`] if an ACL has [`AclKey::WireGuardPeer`] keys and the
+    /// [`Config::wireguard_peers_path`] file can't be read.
+    ///
+    /// Returns [`Error::InvalidConfig`] if there are no zones, or two zones with the same domain,
+    /// if the TXT store kind requires a state path that isn't set, or isn't supported by this
+    /// build, if DNS-over-TLS is missing a certificate or key path, or isn't supported by this
+    /// build, if the DNS UDP payload size is less than 512, if a rate limit prefix length is too
+    /// long, if DNSSEC signing isn't supported by this build or has a signature validity under an
+    /// hour, if an ACL has peer public keys without a valid [`Config::wireguard_peers_path`] file,
+    /// if a CNAME alias is invalid, or if a CAA record is for a name without other records or has
+    /// an invalid value.
     pub fn try_from_file(p: impl AsRef<Path>) -> Result<Self, Error> {
         let f = File::open(p)?;
         let reader = BufReader::new(f);
-        let conf: Config = serde_json::from_reader(reader)?;
+        let mut value: Value = serde_json::from_reader(reader)?;
+        move_top_level_zone(&mut value);
+        let mut conf: Config = serde_json::from_value(value)?;
         conf.bind_addr_is_secure()?;
+        conf.txt_store_kind_is_valid()?;
+        conf.dns_tls_is_valid()?;
+        if conf.dns_max_udp_payload < 512 {
+            return Err(Error::InvalidConfig(format!(
+                "dns_max_udp_payload ({}) must be at least 512",
+                conf.dns_max_udp_payload
+            )));
+        }
+        if conf.txt_max_age == Some(Duration::ZERO) {
+            return Err(Error::InvalidConfig(
+                "txt_max_age must be at least 1 second".to_string(),
+            ));
+        }
+        conf.rate_limit_is_valid()?;
+        conf.dnssec_is_valid()?;
+        conf.zones_are_valid()?;
+        conf.wireguard_allowed_ips = conf.load_wireguard_peers()?;
+        for zone in &mut conf.zones {
+            zone.load().map_err(|err| match err {
+                Error::InvalidConfig(reason) => {
+                    Error::InvalidConfig(format!("zone {}: {reason}", zone.domain))
+                }
+                err => err,
+            })?;
+        }
         Ok(conf)
     }
 
+    /// Returns the [`Zone`] with the longest [`Zone::domain`] the given FQDN is within (if any).
     #[must_use]
-    /// Checks if the given [`IpAddr`] is allowed to update the given [`Name`] based on the
-    /// configuration ACL.
-    pub fn update_permitted(&self, source_ip: IpAddr, subdomain: &Name) -> bool {
-        self.acl
+    pub fn zone(&self, fqdn: &LowerName) -> Option<&Zone> {
+        self.zones
             .iter()
-            .any(|(allowed_network, allowed_subdomains)| {
-                allowed_network.contains(source_ip)
-                    && allowed_subdomains.contains(&LowerName::from(subdomain))
-            })
+            .filter(|zone| zone.domain.zone_of(fqdn))
+            .max_by_key(|zone| zone.domain.num_labels())
     }
 
-    /// Returns the contact email as of the nameserver administrator as a [Name], or an error
-    /// if the configured ns admin isn't a valid [Name].
-    ///
-    /// The '@' symbol in the contact email will be replaced by a '.'. Any '.' that appear in the
-    /// user portion of the email address will be escaped to '\\.'.
+    /// Returns the first of the [`Config::zones`], used by the [HTTP API][crate::api] when an
+    /// update or registration doesn't specify a zone.
     ///
-    /// # Errors
+    /// # Panics
     ///
-    /// Returns [`Error::DNSError`] if the configured ns admin string can't be converted to a
-    /// [`Name`].
-    pub fn ns_admin(&self) -> Result<Name, Error> {
-        Ok(Name::from_str(&self.sanitized_ns_admin())?)
+    /// Never panics for a loaded config, which always has at least one zone.
+    #[must_use]
+    pub fn default_zone(&self) -> &Zone {
+        self.zones.first().expect("config has no zones")
+    }
+
+    /// Checks if the given [`IpAddr`] is allowed to request a zone transfer based on the
+    /// [`Config::transfer_allowed`] networks.
+    #[must_use]
+    pub fn transfer_permitted(&self, source_ip: IpAddr) -> bool {
+        self.transfer_allowed
+            .iter()
+            .any(|allowed_network| allowed_network.contains(source_ip))
+    }
+
+    /// Describe the differences between this [Config] and another, one change per line. Changes
+    /// to map values (e.g. [`Zone::acl`]) are described per key, and changes to zones are
+    /// described per [`Zone::domain`]. Changes to the allowed IPs of [`AclKey::WireGuardPeer`]s
+    /// in both configs are described per peer.
+    #[must_use]
+    pub fn diff(&self, other: &Config) -> Vec<String> {
+        let (Ok(Value::Object(mut old)), Ok(Value::Object(mut new))) =
+            (serde_json::to_value(self), serde_json::to_value(other))
+        else {
+            return Vec::default();
+        };
+        for config in [&mut old, &mut new] {
+            if let Some(Value::Array(zones)) = config.remove("zones") {
+                config.insert("zones".to_string(), zones_by_domain(zones));
+            }
+        }
+        let mut changes = Vec::default();
+        diff_values("", &Value::Object(old), &Value::Object(new), &mut changes);
+
+        // NB: allowed IPs are read from the WireGuard peers file, so can change without the config.
+        let allowed_ips = |config: &Config, peer: &String| -> Value {
+            config
+                .wireguard_allowed_ips
+                .get(peer)
+                .into_iter()
+                .flatten()
+                .map(|network| Value::String(network.to_string()))
+                .collect()
+        };
+        let old_peers: HashSet<&String> = self.wireguard_peers().collect();
+        let mut peers: Vec<&String> = other
+            .wireguard_peers()
+            .filter(|peer| old_peers.contains(peer))
+            .collect();
+        peers.sort();
+        peers.dedup();
+        for peer in peers {
+            diff_values(
+                &format!("wireguard_peers.{peer}"),
+                &allowed_ips(self, peer),
+                &allowed_ips(other, peer),
+                &mut changes,
+            );
+        }
+        changes
     }
 
-    /// Return a [`DynTxtStore`] based on the configuration. If a [`Config::txt_store_state_path`] is
-    /// set, a [`FileTxtStore`] is constructed using the path. Otherwise, a [`InMemoryTxtStore`] is
-    /// used.
+    /// Returns the networks of the given [`AclKey`]. For a [`AclKey::WireGuardPeer`] these are
+    /// the allowed IPs of the peer, or none if the peer isn't in the
+    /// [`Config::wireguard_peers_path`] file.
+    #[must_use]
+    pub fn acl_networks<'a>(&'a self, key: &'a AclKey) -> &'a [IpNetwork] {
+        match key {
+            AclKey::Network(network) => std::slice::from_ref(network),
+            AclKey::WireGuardPeer(public_key) => self
+                .wireguard_allowed_ips
+                .get(public_key)
+                .map_or(&[], Vec::as_slice),
+        }
+    }
+
+    #[must_use]
+    /// Checks if the given [`IpAddr`] is allowed to update the given [`Name`] in the given
+    /// [`Zone`] based on the zone's ACL.
+    pub fn update_permitted(&self, zone: &Zone, source_ip: IpAddr, subdomain: &Name) -> bool {
+        zone.acl.iter().any(|(key, allowed_subdomains)| {
+            self.acl_networks(key)
+                .iter()
+                .any(|allowed_network| allowed_network.contains(source_ip))
+                && allowed_subdomains
+                    .iter()
+                    .any(|pattern| pattern.matches(subdomain))
+        })
+    }
+
+    /// Returns the [`TxtStoreKind`] in use. This is the [`Config::txt_store_kind`] if set,
+    /// otherwise [`TxtStoreKind::File`] when a [`Config::txt_store_state_path`] is set and
+    /// [`TxtStoreKind::Memory`] when it isn't.
+    #[must_use]
+    pub fn txt_store_kind(&self) -> TxtStoreKind {
+        match (self.txt_store_kind, &self.txt_store_state_path) {
+            (Some(kind), _) => kind,
+            (None, Some(_)) => TxtStoreKind::File,
+            (None, None) => TxtStoreKind::Memory,
+        }
+    }
+
+    /// Return a [`DynTxtStore`] based on the configuration's [`Config::txt_store_kind`]. File and
+    /// SQLite-backed stores are constructed using the [`Config::txt_store_state_path`]. In all
+    /// cases the [`Config::txt_max_age`] is applied to the store.
     ///
     /// # Errors
     ///
-    /// Returns [`Error`] if [`FileTxtStore::try_from_file`] fails.
+    /// Returns [`Error`] if [`FileTxtStore::try_from_file`] or
+    /// `SqliteTxtStore::try_from_file` fails.
+    ///
+    /// Returns [`Error::InvalidConfig`] if the TXT store kind requires a state path that isn't
+    /// set, or isn't supported by this build.
     pub async fn txt_store(&self) -> Result<DynTxtStore, Error> {
-        match &self.txt_store_state_path {
-            Some(state_path) => {
+        self.txt_store_kind_is_valid()?;
+        let state_path = self.txt_store_state_path.as_deref().unwrap_or_default();
+        match self.txt_store_kind() {
+            TxtStoreKind::Memory => {
+                tracing::debug!("using in-memory txt store");
+                Ok(Arc::new(RwLock::new(InMemoryTxtStore::new(
+                    self.txt_max_age,
+                ))))
+            }
+            TxtStoreKind::File => {
                 tracing::debug!("using file-backed txt store: {state_path:?}");
                 Ok(Arc::new(RwLock::new(
-                    FileTxtStore::try_from_file(state_path).await?,
+                    FileTxtStore::try_from_file(state_path, self.txt_max_age).await?,
                 )))
             }
-            None => {
-                tracing::debug!("using in-memory txt store");
-                Ok(Arc::new(RwLock::new(InMemoryTxtStore::default())))
+            #[cfg(feature = "sqlite")]
+            TxtStoreKind::Sqlite => {
+                tracing::debug!("using sqlite txt store: {state_path:?}");
+                Ok(Arc::new(RwLock::new(
+                    crate::txt_store::SqliteTxtStore::try_from_file(state_path, self.txt_max_age)
+                        .await?,
+                )))
             }
+            #[cfg(not(feature = "sqlite"))]
+            TxtStoreKind::Sqlite => unreachable!("rejected by txt_store_kind_is_valid"),
         }
     }
 
-    fn sanitized_ns_admin(&self) -> Cow<str> {
-        match self.ns_admin.split_once('@') {
-            Some((user, domain)) => {
-                let user = user.replace('.', "\\.");
-                Cow::Owned(format!("{user}.{domain}"))
+    // Returns the public keys of the WireGuard peers in every zone's ACL.
+    fn wireguard_peers(&self) -> impl Iterator<Item = &String> {
+        self.zones
+            .iter()
+            .flat_map(|zone| zone.acl.keys())
+            .filter_map(|key| match key {
+                AclKey::WireGuardPeer(public_key) => Some(public_key),
+                AclKey::Network(_) => None,
+            })
+    }
+
+    fn load_wireguard_peers(&self) -> Result<HashMap<String, Vec<IpNetwork>>, Error> {
+        let peers: Vec<&String> = self.wireguard_peers().collect();
+        if peers.is_empty() {
+            return Ok(HashMap::default());
+        }
+        let Some(path) = &self.wireguard_peers_path else {
+            return Err(Error::InvalidConfig(
+                "acl has WireGuard peer keys, but wireguard_peers_path isn't set".to_string(),
+            ));
+        };
+        let allowed_ips =
+            parse_wireguard_peers(&std::fs::read_to_string(path)?).map_err(|reason| {
+                Error::InvalidConfig(format!("invalid WireGuard peers file {path}: {reason}"))
+            })?;
+        // NB: a missing peer isn't an error, so removing a peer from the tunnel doesn't prevent the
+        // config loading. It has no networks, so can't update anything.
+        for peer in peers {
+            if !allowed_ips.contains_key(peer) {
+                tracing::warn!("acl WireGuard peer {peer} not found in {path}");
             }
-            _ => Cow::Borrowed(&self.ns_admin),
         }
+        Ok(allowed_ips)
+    }
+
+    fn zones_are_valid(&self) -> Result<(), Error> {
+        if self.zones.is_empty() {
+            return Err(Error::InvalidConfig(
+                "at least one zone must be configured".to_string(),
+            ));
+        }
+        let mut domains = HashSet::new();
+        for zone in &self.zones {
+            if !domains.insert(&zone.domain) {
+                return Err(Error::InvalidConfig(format!(
+                    "zone {} is configured more than once",
+                    zone.domain
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn txt_store_kind_is_valid(&self) -> Result<(), Error> {
+        let kind = self.txt_store_kind();
+        if kind == TxtStoreKind::Sqlite && !cfg!(feature = "sqlite") {
+            return Err(Error::InvalidConfig(
+                "txt_store_kind \"sqlite\" requires building with the sqlite feature".to_string(),
+            ));
+        }
+        if kind != TxtStoreKind::Memory && self.txt_store_state_path.is_none() {
+            return Err(Error::InvalidConfig(format!(
+                "txt_store_kind \"{}\" requires a txt_store_state_path",
+                format!("{kind:?}").to_lowercase()
+            )));
+        }
+        Ok(())
+    }
+
+    fn dns_tls_is_valid(&self) -> Result<(), Error> {
+        if self.dns_tls_bind_addr.is_none() {
+            return Ok(());
+        }
+        if !cfg!(feature = "dns-over-tls") {
+            return Err(Error::InvalidConfig(
+                "dns_tls_bind_addr requires building with the dns-over-tls feature".to_string(),
+            ));
+        }
+        if self.dns_tls_cert_path.is_none() || self.dns_tls_key_path.is_none() {
+            return Err(Error::InvalidConfig(
+                "dns_tls_bind_addr requires a dns_tls_cert_path and dns_tls_key_path".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn rate_limit_is_valid(&self) -> Result<(), Error> {
+        let Some(rate_limit) = &self.rate_limit else {
+            return Ok(());
+        };
+        if rate_limit.ipv4_prefix_len > 32 {
+            return Err(Error::InvalidConfig(format!(
+                "rate_limit.ipv4_prefix_len ({}) must be at most 32",
+                rate_limit.ipv4_prefix_len
+            )));
+        }
+        if rate_limit.ipv6_prefix_len > 128 {
+            return Err(Error::InvalidConfig(format!(
+                "rate_limit.ipv6_prefix_len ({}) must be at most 128",
+                rate_limit.ipv6_prefix_len
+            )));
+        }
+        Ok(())
+    }
+
+    fn dnssec_is_valid(&self) -> Result<(), Error> {
+        let Some(dnssec) = &self.dnssec else {
+            return Ok(());
+        };
+        if !cfg!(feature = "dnssec") {
+            return Err(Error::InvalidConfig(
+                "dnssec requires building with the dnssec feature".to_string(),
+            ));
+        }
+        if dnssec.signature_validity.as_secs() < 3600 {
+            return Err(Error::InvalidConfig(format!(
+                "dnssec.signature_validity ({}) must be at least 3600",
+                dnssec.signature_validity.as_secs()
+            )));
+        }
+        Ok(())
     }
 
     fn bind_addr_is_secure(&self) -> Result<(), Error> {
@@ -188,3 +1228,531 @@ impl Config {
         }
     }
 }
+
+impl Zone {
+    // Validate the zone, and derive the TXT domains and CAA record data used to answer queries.
+    fn load(&mut self) -> Result<(), Error> {
+        self.tsig_keys_are_valid()?;
+        self.txt_domain_set = self.build_txt_domain_set()?;
+        self.txt_domain_patterns = self.build_txt_domain_patterns();
+        self.txt_domain_suffixes = self
+            .txt_domain_patterns
+            .iter()
+            .map(|pattern| {
+                Ok(pattern
+                    .suffix()
+                    .append_domain(&(&self.domain).into())?
+                    .into())
+            })
+            .collect::<Result<_, Error>>()?;
+        self.cnames_are_valid()?;
+        self.ttls_are_valid()?;
+        self.caa_rdata = self.build_caa_rdata()?;
+        Ok(())
+    }
+
+    /// Checks if the given FQDN is a subdomain listed in, or matching a pattern in, the
+    /// [`Zone::acl`].
+    #[must_use]
+    pub fn is_txt_domain(&self, fqdn: &LowerName) -> bool {
+        if self.txt_domain_set.contains(fqdn) {
+            return true;
+        }
+        if self.txt_domain_patterns.is_empty() || !self.domain.zone_of(fqdn) {
+            return false;
+        }
+        let name = Name::from(fqdn);
+        let relative = usize::from(name.num_labels() - self.domain.num_labels());
+        let labels: Vec<&[u8]> = name.iter().take(relative).collect();
+        !labels.is_empty()
+            && self
+                .txt_domain_patterns
+                .iter()
+                .any(|pattern| pattern.matches_labels(&labels))
+    }
+
+    /// Returns the fully qualified names of every exact subdomain in the [`Zone::acl`], and the
+    /// names every subdomain matching an ACL pattern is below (see [`SubdomainPattern::suffix`]).
+    pub fn txt_domain_names(&self) -> impl Iterator<Item = &LowerName> {
+        self.txt_domain_set
+            .iter()
+            .chain(self.txt_domain_suffixes.difference(&self.txt_domain_set))
+    }
+
+    /// Returns the CAA records to serve for the given FQDN (if any).
+    #[must_use]
+    pub fn caa(&self, fqdn: &LowerName) -> &[CAA] {
+        self.caa_rdata.get(fqdn).map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns the [`TsigKey`] with the given name (if any).
+    #[must_use]
+    pub fn tsig_key(&self, name: &LowerName) -> Option<&TsigKey> {
+        // NB: compared by label so that key names match regardless of a trailing '.'.
+        self.tsig_keys
+            .iter()
+            .find(|(key_name, _)| *key_name == name)
+            .map(|(_, key)| key)
+    }
+
+    /// Checks if the [`TsigKey`] with the given name is allowed to update the TXT records of the
+    /// given FQDN.
+    #[must_use]
+    pub fn tsig_update_permitted(&self, key_name: &LowerName, fqdn: &LowerName) -> bool {
+        let domain: Name = (&self.domain).into();
+        self.tsig_key(key_name).map_or(false, |key| {
+            key.subdomains.iter().any(|subdomain| {
+                Name::from(subdomain)
+                    .append_domain(&domain)
+                    .map_or(false, |allowed| LowerName::from(allowed) == *fqdn)
+            })
+        })
+    }
+
+    /// Returns the contact email as of the nameserver administrator as a [Name], or an error
+    /// if the configured ns admin isn't a valid [Name].
+    ///
+    /// The '@' symbol in the contact email will be replaced by a '.'. Any '.' that appear in the
+    /// user portion of the email address will be escaped to '\\.'.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::DNSError`] if the configured ns admin string can't be converted to a
+    /// [`Name`].
+    pub fn ns_admin(&self) -> Result<Name, Error> {
+        Ok(Name::from_str(&self.sanitized_ns_admin())?)
+    }
+
+    fn sanitized_ns_admin(&self) -> Cow<str> {
+        match self.ns_admin.split_once('@') {
+            Some((user, domain)) => {
+                let user = user.replace('.', "\\.");
+                Cow::Owned(format!("{user}.{domain}"))
+            }
+            _ => Cow::Borrowed(&self.ns_admin),
+        }
+    }
+
+    fn build_txt_domain_set(&self) -> Result<HashSet<LowerName>, Error> {
+        let domain: Name = (&self.domain).into();
+        let mut txt_domain_set = HashSet::default();
+        let acl_subdomains = self
+            .acl
+            .values()
+            .flatten()
+            .filter_map(SubdomainPattern::exact_name);
+        let tsig_subdomains = self
+            .tsig_keys
+            .values()
+            .flat_map(|key| &key.subdomains)
+            .map(Name::from);
+        for subdomain in acl_subdomains.chain(tsig_subdomains) {
+            txt_domain_set.insert(subdomain.append_domain(&domain)?.into());
+        }
+        Ok(txt_domain_set)
+    }
+
+    fn build_txt_domain_patterns(&self) -> Vec<SubdomainPattern> {
+        let mut patterns: Vec<SubdomainPattern> = self
+            .acl
+            .values()
+            .flatten()
+            .filter(|pattern| pattern.exact_name().is_none())
+            .cloned()
+            .collect();
+        patterns.sort_by(|a, b| a.pattern.cmp(&b.pattern));
+        patterns.dedup();
+        patterns
+    }
+
+    fn build_caa_rdata(&self) -> Result<HashMap<LowerName, Vec<CAA>>, Error> {
+        let mut caa_rdata = HashMap::default();
+        for (fqdn, records) in &self.caa_records {
+            if *fqdn != self.domain && !self.addrs.contains_key(fqdn) {
+                return Err(Error::InvalidConfig(format!(
+                    "CAA records for \"{fqdn}\" must be for {} or a name in addrs",
+                    self.domain
+                )));
+            }
+            let rdata = records
+                .iter()
+                .map(|record| {
+                    record.to_rdata().map_err(|err| {
+                        Error::InvalidConfig(format!(
+                            "invalid CAA {} value \"{}\" for \"{fqdn}\": {err}",
+                            format!("{:?}", record.tag).to_lowercase(),
+                            record.value
+                        ))
+                    })
+                })
+                .collect::<Result<_, _>>()?;
+            caa_rdata.insert(fqdn.clone(), rdata);
+        }
+        Ok(caa_rdata)
+    }
+
+    fn tsig_keys_are_valid(&self) -> Result<(), Error> {
+        for (name, key) in &self.tsig_keys {
+            if key.secret_bytes()?.is_empty() {
+                return Err(Error::InvalidConfig(format!(
+                    "TSIG key \"{name}\" has an empty secret"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    // TTLs and SOA timers are limited to 2^31 - 1 seconds by RFC 2181, and the SOA timers must be
+    // consistent so secondaries retry before they refresh, and refresh before the zone expires.
+    fn ttls_are_valid(&self) -> Result<(), Error> {
+        let ttls = &self.ttls;
+        let timers = &self.soa_timers;
+        let values = [
+            ("ttls.txt", ttls.txt),
+            ("ttls.static", ttls.static_records),
+            ("ttls.ns", ttls.ns),
+            ("ttls.soa", ttls.soa),
+            ("soa_timers.refresh", timers.refresh),
+            ("soa_timers.retry", timers.retry),
+            ("soa_timers.expire", timers.expire),
+            ("soa_timers.minimum", timers.minimum),
+        ];
+        for (key, value) in values {
+            if value > MAX_TTL {
+                return Err(Error::InvalidConfig(format!(
+                    "{key} ({value}) must be at most {MAX_TTL}"
+                )));
+            }
+        }
+        if timers.refresh == 0 || timers.retry == 0 {
+            return Err(Error::InvalidConfig(
+                "soa_timers.refresh and soa_timers.retry must be greater than 0".to_string(),
+            ));
+        }
+        if timers.retry > timers.refresh {
+            return Err(Error::InvalidConfig(format!(
+                "soa_timers.retry ({}) must not exceed soa_timers.refresh ({})",
+                timers.retry, timers.refresh
+            )));
+        }
+        if timers.expire < timers.refresh + timers.retry {
+            return Err(Error::InvalidConfig(format!(
+                "soa_timers.expire ({}) must be at least soa_timers.refresh + soa_timers.retry ({})",
+                timers.expire,
+                timers.refresh + timers.retry
+            )));
+        }
+        Ok(())
+    }
+
+    fn cnames_are_valid(&self) -> Result<(), Error> {
+        for alias in self.cnames.keys() {
+            if !self.domain.zone_of(alias) || *alias == self.domain {
+                return Err(Error::InvalidConfig(format!(
+                    "CNAME alias \"{alias}\" must be a subdomain of {}",
+                    self.domain
+                )));
+            }
+            if self.addrs.contains_key(alias)
+                || self.ns_records.contains_key(alias)
+                || self.is_txt_domain(alias)
+            {
+                return Err(Error::InvalidConfig(format!(
+                    "CNAME alias \"{alias}\" can't have other records"
+                )));
+            }
+            let mut target = alias;
+            for _ in 0..self.cnames.len() {
+                match self.cnames.get(target) {
+                    Some(next) => target = next,
+                    None => break,
+                }
+            }
+            if self.cnames.contains_key(target) {
+                return Err(Error::InvalidConfig(format!(
+                    "CNAME alias \"{alias}\" leads to a CNAME loop"
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl DynConfig {
+    /// Try to load a [`DynConfig`] from the provided path.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [Error] if [`Config::try_from_file`] fails.
+    pub fn try_from_file(p: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = p.as_ref().to_path_buf();
+        let config = Config::try_from_file(&path)?;
+        Ok(Self {
+            path,
+            current: Arc::new(StdRwLock::new(Arc::new(config))),
+        })
+    }
+
+    /// Returns the current [Shared] config. The returned config is unaffected by later reloads.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lock guarding the current config was poisoned.
+    #[must_use]
+    pub fn current(&self) -> Shared {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Re-read and validate the config file, replacing the current config if it is valid.
+    /// Returns a description of what changed. If the config is invalid the current config is
+    /// kept.
+    ///
+    /// Changes to bind addresses, timeouts, TLS settings, DNSSEC keys, and the TXT store settings
+    /// are logged but don't take effect until restart.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [Error] if [`Config::try_from_file`] fails.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lock guarding the current config was poisoned.
+    pub fn reload(&self) -> Result<Vec<String>, Error> {
+        let new_config = Config::try_from_file(&self.path)?;
+        let mut current = self.current.write().unwrap();
+        let changes = current.diff(&new_config);
+        for change in &changes {
+            tracing::info!("config changed: {change}");
+        }
+        for key in restart_required_keys(&changes) {
+            tracing::warn!("config change to {key} will not take effect until restart");
+        }
+        *current = Arc::new(new_config);
+        Ok(changes)
+    }
+}
+
+// Returns the keys of RESTART_REQUIRED_KEYS that have changed, including nested changes such as
+// "dnssec.ksk_path".
+fn restart_required_keys(changes: &[String]) -> Vec<&'static str> {
+    RESTART_REQUIRED_KEYS
+        .iter()
+        .copied()
+        .filter(|key| {
+            changes.iter().any(|change| {
+                change
+                    .strip_prefix(key)
+                    .map_or(false, |rest| rest.starts_with(':') || rest.starts_with('.'))
+            })
+        })
+        .collect()
+}
+
+// Move the zone keys at the top level of a config file into a zone at the start of its zones.
+fn move_top_level_zone(config: &mut Value) {
+    let Value::Object(config) = config else {
+        return;
+    };
+    let zone: serde_json::Map<String, Value> = ZONE_KEYS
+        .iter()
+        .filter_map(|key| Some(((*key).to_string(), config.remove(*key)?)))
+        .collect();
+    if zone.is_empty() {
+        return;
+    }
+    // NB: zones that aren't a list are left for deserialization to reject.
+    if let Value::Array(zones) = config
+        .entry("zones")
+        .or_insert_with(|| Value::Array(Vec::default()))
+    {
+        zones.insert(0, Value::Object(zone));
+    }
+}
+
+// Key a list of serialized zones by their domain, without the trailing '.', so that changes are
+// described per zone.
+fn zones_by_domain(zones: Vec<Value>) -> Value {
+    zones
+        .into_iter()
+        .map(|zone| {
+            let domain = zone["domain"].as_str().unwrap_or_default();
+            (domain.trim_end_matches('.').to_string(), zone)
+        })
+        .collect::<serde_json::Map<_, _>>()
+        .into()
+}
+
+// Parse the public key and allowed IPs of each peer from a WireGuard interface config file, or
+// the output of `wg show <interface> dump`. Config files are recognised by their sections.
+fn parse_wireguard_peers(contents: &str) -> Result<HashMap<String, Vec<IpNetwork>>, String> {
+    let parse_ips = |ips: &str| {
+        ips.split(',')
+            .map(str::trim)
+            .filter(|ip| !ip.is_empty() && *ip != "(none)")
+            .map(|ip| {
+                IpNetwork::from_str(ip).map_err(|err| format!("invalid allowed IP {ip}: {err}"))
+            })
+            .collect::<Result<Vec<_>, _>>()
+    };
+    let mut peers: HashMap<String, Vec<IpNetwork>> = HashMap::default();
+
+    if !contents
+        .lines()
+        .any(|line| line.trim_start().starts_with('['))
+    {
+        // A tab separated line for the interface (4 fields), then one per peer (8 fields).
+        for (i, line) in contents.lines().enumerate() {
+            match line.split('\t').collect::<Vec<_>>().as_slice() {
+                [] | [""] | [_, _, _, _] => {}
+                [public_key, _, _, allowed_ips, _, _, _, _] => {
+                    peers
+                        .entry((*public_key).to_string())
+                        .or_default()
+                        .extend(parse_ips(allowed_ips)?);
+                }
+                _ => return Err(format!("line {} isn't a wg show dump line", i + 1)),
+            }
+        }
+        return Ok(peers);
+    }
+
+    // The peers of a config file, each with an optional public key until the section ends.
+    let mut sections: Vec<(Option<String>, Vec<IpNetwork>)> = Vec::default();
+    let mut in_peer = false;
+    for line in contents.lines() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.starts_with('[') {
+            in_peer = line.eq_ignore_ascii_case("[peer]");
+            if in_peer {
+                sections.push((None, Vec::default()));
+            }
+            continue;
+        }
+        let (Some((public_key, allowed_ips)), Some((key, value))) = (
+            sections.last_mut().filter(|_| in_peer),
+            line.split_once('='),
+        ) else {
+            continue;
+        };
+        // NB: keys are case insensitive, and AllowedIPs may be repeated.
+        match key.trim().to_ascii_lowercase().as_str() {
+            "publickey" => *public_key = Some(value.trim().to_string()),
+            "allowedips" => allowed_ips.extend(parse_ips(value)?),
+            _ => {}
+        }
+    }
+    for (public_key, allowed_ips) in sections {
+        let public_key = public_key.ok_or("a [Peer] section has no PublicKey")?;
+        peers.entry(public_key).or_default().extend(allowed_ips);
+    }
+    Ok(peers)
+}
+
+// Recursively describe the differences between two JSON values. Arrays are compared without
+// regard to order since sets (e.g. ACL subdomains) serialize in an arbitrary order.
+fn diff_values(path: &str, old: &Value, new: &Value, changes: &mut Vec<String>) {
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => {
+            let mut keys: Vec<&String> = old.keys().chain(new.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                let key_path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{path}.{key}")
+                };
+                match (old.get(key), new.get(key)) {
+                    (Some(old), Some(new)) => diff_values(&key_path, old, new, changes),
+                    (None, Some(new)) => changes.push(format!("{key_path}: added {new}")),
+                    (Some(old), None) => changes.push(format!("{key_path}: removed {old}")),
+                    (None, None) => {}
+                }
+            }
+        }
+        (Value::Array(old_items), Value::Array(new_items)) => {
+            let sorted = |items: &Vec<Value>| {
+                let mut items: Vec<String> = items.iter().map(Value::to_string).collect();
+                items.sort();
+                items
+            };
+            if sorted(old_items) != sorted(new_items) {
+                changes.push(format!("{path}: {old} -> {new}"));
+            }
+        }
+        _ if old != new => changes.push(format!("{path}: {old} -> {new}")),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // A minimal valid config with a single zone given at the top level.
+    fn minimal_config() -> Value {
+        json!({
+            "domain": "pki.example.com",
+            "ns_domain": "ns1.pki.example.com",
+            "ns_admin": "dns-admin@example.com",
+            "api_bind_addr": "127.0.0.1:3000",
+            "api_timeout": 120,
+            "dns_udp_bind_addr": "127.0.0.1:5353",
+            "dns_tcp_bind_addr": "127.0.0.1:5353",
+            "dns_tcp_timeout": 60,
+            "acl": { "127.0.0.1/32": [ "test" ] },
+            "addrs": { "pki.example.com": [ "93.184.216.34" ] },
+            "ns_records": { "pki.example.com": [ "ns1.pki.example.com" ] }
+        })
+    }
+
+    // Writes the config to a new temporary file, returning its path.
+    fn write_config(config: &Value) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("acmecrab-{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(&path, config.to_string()).unwrap();
+        path
+    }
+
+    fn load(config: &Value) -> Result<Config, Error> {
+        let path = write_config(config);
+        let loaded = Config::try_from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        loaded
+    }
+
+    #[test]
+    fn txt_max_age_must_be_positive() {
+        let mut config = minimal_config();
+        config["txt_max_age"] = json!(0);
+        assert!(matches!(load(&config), Err(Error::InvalidConfig(_))));
+
+        config["txt_max_age"] = json!(1);
+        let loaded = load(&config).unwrap();
+        assert_eq!(loaded.txt_max_age, Some(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn nested_changes_require_restart() {
+        let old = json!({
+            "api_timeout": 120,
+            "dnssec": { "ksk_path": "ksk.pem", "zsk_path": "zsk.pem" },
+            "zones": {},
+        });
+        let new = json!({
+            "api_timeout": 120,
+            "dnssec": { "ksk_path": "new-ksk.pem", "zsk_path": "zsk.pem" },
+            "dns_tls_cert_path": "cert.pem",
+            "zones": { "pki.example.com": { "ttls": { "txt": 60 } } },
+        });
+        let mut changes = Vec::default();
+        diff_values("", &old, &new, &mut changes);
+        assert_eq!(
+            restart_required_keys(&changes),
+            ["dns_tls_cert_path", "dnssec"]
+        );
+
+        let changes = ["api_timeout: 120 -> 60".to_string()];
+        assert_eq!(restart_required_keys(&changes), ["api_timeout"]);
+    }
+}
@@ -0,0 +1,402 @@
+use crate::config::{Config, DnssecAlgorithm};
+use crate::error::Error;
+use base64::engine::general_purpose;
+use base64::Engine;
+use ring::signature::{
+    EcdsaKeyPair, Ed25519KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING, ECDSA_P384_SHA384_FIXED_SIGNING,
+};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use trust_dns_client::rr::dnssec::{tbs, Algorithm, DigestType, KeyPair, Private};
+use trust_dns_server::client::rr::rdata::{DNSSECRData, DNSKEY, NSEC, SIG};
+use trust_dns_server::client::rr::{DNSClass, LowerName, Name, RData, Record, RecordType};
+
+// The maximum length of a name in wire format, and of each of its labels.
+const MAX_NAME_LEN: usize = 255;
+const MAX_LABEL_LEN: usize = 63;
+
+// How far in the past signatures are valid from, to allow for clock skew between ACME Crab and
+// validating resolvers.
+const INCEPTION_OFFSET: Duration = Duration::from_secs(3600);
+
+/// The names in the zone and the types of their records, in canonical order. Used to find the
+/// closest encloser of a name, and the types listed by the NSEC records that prove a name or type
+/// doesn't exist.
+pub(super) type NsecChain = BTreeMap<LowerName, BTreeSet<RecordType>>;
+
+/// `ZoneSigner` signs responses for every [`Zone::domain`][crate::config::Zone::domain] zone
+/// online with the keys from [`Config::dnssec`]. The KSK signs the DNSKEY records, and the ZSK
+/// all other records.
+pub(super) struct ZoneSigner {
+    algorithm: Algorithm,
+    ksk: SigningKey,
+    zsk: SigningKey,
+    signature_validity: Duration,
+}
+
+struct SigningKey {
+    dnskey: DNSKEY,
+    key_tag: u16,
+    key_pair: KeyPair<Private>,
+}
+
+impl ZoneSigner {
+    /// Load the KSK and ZSK from the [`Config::dnssec`] paths, or return `None` if DNSSEC isn't
+    /// configured.
+    pub(super) fn from_config(config: &Config) -> Result<Option<Arc<Self>>, Error> {
+        let Some(dnssec) = &config.dnssec else {
+            return Ok(None);
+        };
+        let algorithm = match dnssec.algorithm {
+            DnssecAlgorithm::Ecdsap256sha256 => Algorithm::ECDSAP256SHA256,
+            DnssecAlgorithm::Ecdsap384sha384 => Algorithm::ECDSAP384SHA384,
+            DnssecAlgorithm::Ed25519 => Algorithm::ED25519,
+        };
+        Ok(Some(Arc::new(Self {
+            algorithm,
+            ksk: SigningKey::load(&dnssec.ksk_path, dnssec.algorithm, algorithm, true)?,
+            zsk: SigningKey::load(&dnssec.zsk_path, dnssec.algorithm, algorithm, false)?,
+            signature_validity: dnssec.signature_validity,
+        })))
+    }
+
+    /// Returns the DS record for the KSK to publish in the parent of the zone, in presentation
+    /// format.
+    pub(super) fn ds(&self, zone: &LowerName) -> Result<String, Error> {
+        let digest = self
+            .ksk
+            .dnskey
+            .to_digest(&zone.into(), DigestType::SHA256)?;
+        let digest = digest.as_ref().iter().fold(String::new(), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02X}");
+            hex
+        });
+        Ok(format!(
+            "{zone} IN DS {} {} {} {digest}",
+            self.ksk.key_tag,
+            u8::from(self.algorithm),
+            u8::from(DigestType::SHA256),
+        ))
+    }
+
+    /// Returns the DNSKEY record data of the KSK and ZSK.
+    pub(super) fn dnskey_rdata(&self) -> Vec<RData> {
+        [&self.ksk, &self.zsk]
+            .into_iter()
+            .map(|key| RData::DNSSEC(DNSSECRData::DNSKEY(key.dnskey.clone())))
+            .collect()
+    }
+
+    /// Returns an RRSIG record for each set of records with the same name and type in the zone.
+    /// Records outside the zone are left unsigned.
+    pub(super) fn sign(&self, zone: &LowerName, records: &[Record]) -> Result<Vec<Record>, Error> {
+        let signer_name = Name::from(zone);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        // NB: RRSIG times are serial numbers, so wrapping in 2106 is expected.
+        #[allow(clippy::cast_possible_truncation)]
+        let (inception, expiration) = (
+            now.saturating_sub(INCEPTION_OFFSET).as_secs() as u32,
+            (now + self.signature_validity).as_secs() as u32,
+        );
+
+        // The TTL of each set of records, which is the original TTL of its RRSIG.
+        let mut rrsets: BTreeMap<(Name, RecordType), u32> = BTreeMap::new();
+        for record in records {
+            if zone.zone_of(&record.name().into()) {
+                let ttl = rrsets
+                    .entry((record.name().clone(), record.rr_type()))
+                    .or_insert(record.ttl());
+                *ttl = (*ttl).min(record.ttl());
+            }
+        }
+        let mut rrsigs = Vec::with_capacity(rrsets.len());
+        for ((name, rr_type), ttl) in rrsets {
+            let key = match rr_type {
+                RecordType::DNSKEY => &self.ksk,
+                _ => &self.zsk,
+            };
+            let num_labels = name.num_labels();
+            let tbs = tbs::rrset_tbs(
+                &name,
+                DNSClass::IN,
+                num_labels,
+                rr_type,
+                self.algorithm,
+                ttl,
+                expiration,
+                inception,
+                key.key_tag,
+                &signer_name,
+                records,
+            )?;
+            let sig = SIG::new(
+                rr_type,
+                self.algorithm,
+                num_labels,
+                ttl,
+                expiration,
+                inception,
+                key.key_tag,
+                signer_name.clone(),
+                key.key_pair.sign(self.algorithm, &tbs)?,
+            );
+            // NB: RRSIG data is a SIG, so the record type must be set explicitly.
+            let mut rrsig = Record::with(name, RecordType::RRSIG, ttl);
+            rrsig.set_data(Some(RData::DNSSEC(DNSSECRData::SIG(sig))));
+            rrsigs.push(rrsig);
+        }
+        Ok(rrsigs)
+    }
+
+    /// Returns the NSEC records proving there are no records of the queried type for name. For
+    /// NXDOMAIN responses these prove the name, and a wildcard at its closest encloser, don't
+    /// exist. Otherwise they prove the name has no records of the type (NODATA).
+    ///
+    /// The records are minimally covering [RFC-4470][RFC-4470] "white lies": rather than linking
+    /// the names in the zone, each spans only the denied name, from the name immediately before it
+    /// to the name immediately after it. So the names in the zone can't be walked by following the
+    /// next names of negative answers.
+    ///
+    /// [RFC-4470]: https://www.rfc-editor.org/rfc/rfc4470
+    pub(super) fn denial(
+        chain: &NsecChain,
+        name: &LowerName,
+        nxdomain: bool,
+        ttl: u32,
+    ) -> Result<Vec<Record>, Error> {
+        if !nxdomain {
+            return Ok(successor(&name.into())
+                .map(|next| Self::nsec(chain, name.into(), next, ttl))
+                .into_iter()
+                .collect());
+        }
+
+        // The closest encloser is the longest ancestor of the name that exists, either with
+        // records or as an empty non-terminal.
+        let mut encloser = name.base_name();
+        while !chain.keys().any(|owner| encloser.zone_of(owner)) && !encloser.is_root() {
+            encloser = encloser.base_name();
+        }
+        let wildcard = Name::from_ascii("*")?.append_domain(&encloser.into())?;
+
+        let mut records = Vec::with_capacity(2);
+        for denied in [name.into(), wildcard] {
+            if let (Some(owner), Some(next)) = (predecessor(&denied), successor(&denied)) {
+                records.push(Self::nsec(chain, owner, next, ttl));
+            }
+        }
+        records.dedup();
+        Ok(records)
+    }
+
+    // Returns the NSEC record for the owner with the given next name, listing the types of the
+    // owner's records in the chain (if any).
+    fn nsec(chain: &NsecChain, owner: Name, next: Name, ttl: u32) -> Record {
+        let mut types = chain
+            .get(&LowerName::from(&owner))
+            .cloned()
+            .unwrap_or_default();
+        types.extend([RecordType::RRSIG, RecordType::NSEC]);
+        Record::from_rdata(
+            owner,
+            ttl,
+            RData::DNSSEC(DNSSECRData::NSEC(NSEC::new(
+                next,
+                types.into_iter().collect(),
+            ))),
+        )
+    }
+}
+
+// Returns the name immediately after name in canonical order, name prefixed with a \000 label
+// (RFC 4471 section 3.1.2), or None if that would be too long.
+fn successor(name: &Name) -> Option<Name> {
+    if wire_len(name.iter()) + 2 > MAX_NAME_LEN {
+        return None;
+    }
+    Name::from_labels([&[0_u8][..]])
+        .and_then(|zero| zero.append_domain(name))
+        .ok()
+}
+
+// Returns a name immediately before name in canonical order (RFC 4471 section 3.1.1), or None for
+// the root. The leftmost label has its last octet decremented, and is padded with \255 octets so
+// no other label sorts between them. A trailing \000 octet is removed instead, with a \255 label
+// prepended to sort after the names below the shortened label. A \000 label is removed, leaving
+// the parent. Unlike RFC 4471, \255 labels aren't otherwise prepended to reach the maximum name
+// length, since names made of \255 octets can't be in the zone and would bloat responses.
+fn predecessor(name: &Name) -> Option<Name> {
+    let mut labels: Vec<Vec<u8>> = name.iter().map(<[u8]>::to_vec).collect();
+    let first = labels.first_mut()?;
+    match first.as_slice() {
+        [0] => {
+            labels.remove(0);
+        }
+        [.., 0] => {
+            first.pop();
+            let room = MAX_NAME_LEN - wire_len(labels.iter().map(Vec::as_slice));
+            labels.insert(0, vec![u8::MAX; MAX_LABEL_LEN.min(room - 1)]);
+        }
+        _ => {
+            // NB: the label isn't empty, it would be the root otherwise.
+            let last = first.last_mut()?;
+            *last -= 1;
+            // Uppercase letters sort as lowercase letters, so are skipped.
+            if last.is_ascii_uppercase() {
+                *last = b'A' - 1;
+            }
+            let room = MAX_NAME_LEN - wire_len(labels.iter().map(Vec::as_slice));
+            let first = &mut labels[0];
+            first.resize(MAX_LABEL_LEN.min(first.len() + room), u8::MAX);
+        }
+    }
+    Name::from_labels(labels.iter().map(Vec::as_slice)).ok()
+}
+
+// Returns the length of a name with the given labels in wire format.
+fn wire_len<'a>(labels: impl Iterator<Item = &'a [u8]>) -> usize {
+    labels.map(|label| label.len() + 1).sum::<usize>() + 1
+}
+
+impl SigningKey {
+    // Load a PKCS#8 private key from a PEM or DER file.
+    fn load(
+        path: &str,
+        kind: DnssecAlgorithm,
+        algorithm: Algorithm,
+        ksk: bool,
+    ) -> Result<Self, Error> {
+        let contents = std::fs::read(path)?;
+        let invalid = |err: &dyn std::fmt::Display| {
+            Error::InvalidConfig(format!("invalid DNSSEC key {path}: {err}"))
+        };
+        let der = if contents.starts_with(b"-----BEGIN") {
+            let base64: String = String::from_utf8_lossy(&contents)
+                .lines()
+                .filter(|line| !line.starts_with("-----"))
+                .collect();
+            general_purpose::STANDARD
+                .decode(base64.trim())
+                .map_err(|err| invalid(&err))?
+        } else {
+            contents
+        };
+
+        // NB: Ed25519 keys from OpenSSL are PKCS#8 v1, without the public key, so aren't checked.
+        let key_pair = match kind {
+            DnssecAlgorithm::Ecdsap256sha256 => {
+                EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &der)
+                    .map(KeyPair::from_ecdsa)
+            }
+            DnssecAlgorithm::Ecdsap384sha384 => {
+                EcdsaKeyPair::from_pkcs8(&ECDSA_P384_SHA384_FIXED_SIGNING, &der)
+                    .map(KeyPair::from_ecdsa)
+            }
+            DnssecAlgorithm::Ed25519 => {
+                Ed25519KeyPair::from_pkcs8_maybe_unchecked(&der).map(KeyPair::from_ed25519)
+            }
+        }
+        .map_err(|err| invalid(&err))?;
+        let dnskey = DNSKEY::new(true, ksk, false, algorithm, key_pair.to_public_bytes()?);
+        Ok(Self {
+            key_tag: dnskey.calculate_key_tag()?,
+            dnskey,
+            key_pair,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn name(name: &str) -> LowerName {
+        LowerName::from_str(name).unwrap()
+    }
+
+    // Returns the name with the given raw labels below example.com.
+    fn raw_name(labels: &[&[u8]]) -> Name {
+        Name::from_labels(labels.iter().copied().chain([&b"example"[..], b"com"])).unwrap()
+    }
+
+    fn chain() -> NsecChain {
+        let mut chain = NsecChain::new();
+        for (owner, rr_type) in [
+            ("pki.example.com.", RecordType::SOA),
+            ("pki.example.com.", RecordType::NS),
+            ("a.pki.example.com.", RecordType::A),
+            ("test.pki.example.com.", RecordType::TXT),
+            ("www.pki.example.com.", RecordType::A),
+        ] {
+            chain.entry(name(owner)).or_default().insert(rr_type);
+        }
+        chain
+    }
+
+    // Returns the owner, next name and types of an NSEC record.
+    fn nsec(record: &Record) -> (LowerName, LowerName, Vec<RecordType>) {
+        let Some(RData::DNSSEC(DNSSECRData::NSEC(nsec))) = record.data() else {
+            panic!("not an NSEC record: {record}");
+        };
+        (
+            record.name().into(),
+            nsec.next_domain_name().into(),
+            nsec.type_bit_maps().to_vec(),
+        )
+    }
+
+    #[test]
+    fn nxdomain_nsec_records_only_cover_denied_names() {
+        let chain = chain();
+        for (missing, wildcard) in [
+            ("missing.pki.example.com.", "*.pki.example.com."),
+            ("b.pki.example.com.", "*.pki.example.com."),
+            ("x.missing.test.pki.example.com.", "*.test.pki.example.com."),
+        ] {
+            let records = ZoneSigner::denial(&chain, &name(missing), true, 60).unwrap();
+            assert_eq!(records.len(), 2, "{missing}");
+            for (record, denied) in records.iter().zip([missing, wildcard]) {
+                let (owner, next, types) = nsec(record);
+                assert!(owner < name(denied) && name(denied) < next, "{record}");
+                assert!(
+                    chain
+                        .keys()
+                        .all(|existing| existing <= &owner || next <= *existing),
+                    "{record} covers a name in the zone"
+                );
+                assert_eq!(types.len(), 2, "{record}");
+            }
+        }
+    }
+
+    #[test]
+    fn nodata_nsec_record_lists_types_of_name() {
+        let owner = name("test.pki.example.com.");
+        let records = ZoneSigner::denial(&chain(), &owner, false, 60).unwrap();
+        assert_eq!(records.len(), 1);
+        let (nsec_owner, next, types) = nsec(&records[0]);
+        assert_eq!(nsec_owner, owner);
+        assert_eq!(next, raw_name(&[b"\0", b"test", b"pki"]).into());
+        assert!(types.contains(&RecordType::TXT));
+    }
+
+    #[test]
+    fn predecessor_sorts_immediately_before_name() {
+        for (before, name) in [
+            (raw_name(&[b"az"]), raw_name(&[b"b"])),
+            (raw_name(&[b"x", b"a"]), raw_name(&[b"a\0"])),
+            (raw_name(&[b"a"]), raw_name(&[b"\0", b"a"])),
+            (raw_name(&[b"@"]), raw_name(&[b"["])),
+        ] {
+            let predecessor = predecessor(&name).unwrap();
+            assert!(before <= predecessor, "{predecessor}");
+            assert!(predecessor < name, "{predecessor}");
+        }
+        assert_eq!(predecessor(&Name::root()), None);
+    }
+}
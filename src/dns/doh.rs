@@ -0,0 +1,78 @@
+use crate::dns::handlers::Handler;
+use crate::error::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use trust_dns_proto::xfer::BufDnsStreamHandle;
+use trust_dns_server::authority::MessageRequest;
+use trust_dns_server::client::op::{Header, Message, ResponseCode};
+use trust_dns_server::client::rr::Record;
+use trust_dns_server::proto::serialize::binary::BinDecodable;
+use trust_dns_server::server::{Protocol, Request, ResponseHandle};
+
+/// `DohHandler` answers [RFC-8484][RFC-8484] DNS-over-HTTPS requests with the handler of a
+/// [`Server`][crate::dns::server::Server], so they share its rate limiter, metrics and
+/// [`Drain`][crate::dns::Drain] with requests over UDP and TCP. The HTTP API uses it to serve the
+/// [`/dns-query` endpoint][crate::api#dns-query-getpost].
+///
+/// [RFC-8484]: https://www.rfc-editor.org/rfc/rfc8484
+#[derive(Clone)]
+pub struct DohHandler {
+    handler: Arc<Handler>,
+}
+
+/// A DNS response to a DNS-over-HTTPS request.
+#[derive(Debug, Clone)]
+pub struct DohResponse {
+    /// The encoded DNS response message.
+    pub message: Vec<u8>,
+    /// How long (in seconds) the response may be cached for, i.e. the lowest TTL of its records.
+    /// `None` if the response has no records.
+    pub max_age: Option<u32>,
+}
+
+impl DohHandler {
+    // Construct a DNS-over-HTTPS handler answering requests with the DNS server's handler.
+    pub(super) fn new(handler: Arc<Handler>) -> Self {
+        DohHandler { handler }
+    }
+
+    /// Answer the encoded DNS request message from `src`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidDoHRequest`] if the request isn't a valid DNS message, or
+    /// [`Error::DNSError`] if the response can't be encoded.
+    pub async fn answer(&self, message: &[u8], src: SocketAddr) -> Result<DohResponse, Error> {
+        let request = MessageRequest::from_bytes(message)
+            .map_err(|err| Error::InvalidDoHRequest(format!("invalid DNS message: {err}")))?;
+        let header = *request.header();
+        let request = Request::new(request, src, Protocol::Https);
+
+        let (stream_handle, mut receiver) = BufDnsStreamHandle::new(src);
+        self.handler
+            .handle_request(&request, message, ResponseHandle::new(src, stream_handle))
+            .await;
+        // NB: responses are sent to the channel without waiting, so any response has been
+        //     received once the handler returns. None is sent if the request failed.
+        let message = if let Ok(Some(response)) = receiver.get_mut().get_mut().try_next() {
+            response.into_parts().0
+        } else {
+            let mut header = Header::response_from_request(&header);
+            header.set_response_code(ResponseCode::ServFail);
+            let mut response = Message::new();
+            response
+                .set_header(header)
+                .add_query(request.query().original().clone());
+            response.to_vec()?
+        };
+
+        let response = Message::from_vec(&message)?;
+        let max_age = response
+            .answers()
+            .iter()
+            .chain(response.name_servers())
+            .map(Record::ttl)
+            .min();
+        Ok(DohResponse { message, max_age })
+    }
+}
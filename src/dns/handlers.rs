@@ -1,205 +1,594 @@
-use crate::config::Shared;
+use crate::config::{Config, DynConfig, SoaSerial, Zone};
+#[cfg(feature = "dnssec")]
+use crate::dns::dnssec::{NsecChain, ZoneSigner};
+use crate::dns::notify::Notifier;
+use crate::dns::rate_limit::{RateLimitAction, RateLimiter, ResponseKind};
+use crate::dns::server::Drain;
+use crate::dns::update;
 use crate::error::Error;
-use crate::txt_store::DynTxtStore;
-use lazy_static::lazy_static;
+use crate::metrics;
+use crate::txt_store::{date_serial, DynTxtStore};
 use std::collections::HashSet;
 use std::net::IpAddr;
-use time::macros::format_description;
-use time::OffsetDateTime;
+use std::sync::Arc;
 use tracing::error;
 use trust_dns_proto::rr::rdata::SOA;
 use trust_dns_server::authority::MessageResponseBuilder;
-use trust_dns_server::client::op::{Header, MessageType, OpCode, ResponseCode};
+use trust_dns_server::client::op::{Edns, Header, Message, MessageType, OpCode, ResponseCode};
 use trust_dns_server::client::rr::rdata::TXT;
 use trust_dns_server::client::rr::{LowerName, Name, RData, Record, RecordType};
-use trust_dns_server::server::{Request, RequestHandler, ResponseHandler, ResponseInfo};
+use trust_dns_server::server::{Protocol, Request, ResponseHandler, ResponseInfo};
 
 #[derive(Clone)]
 pub struct Handler {
-    config: Shared,
-    txt_domain_set: HashSet<LowerName>,
+    config: DynConfig,
     txt_store: DynTxtStore,
+    notifier: Notifier,
+    drain: Drain,
+    rate_limiter: Arc<RateLimiter>,
+    #[cfg(feature = "dnssec")]
+    signer: Option<Arc<ZoneSigner>>,
 }
 
-lazy_static! {
-    static ref SERIAL_FORMATTER: &'static [time::format_description::FormatItem<'static>] =
-        format_description!(version = 2, "[year][month][day]");
-}
+// The only EDNS version supported. Requests using a later version are answered with BADVERS.
+const SUPPORTED_EDNS_VERSION: u8 = 0;
+
+// The maximum UDP response size for clients that don't use EDNS, and the minimum for those that do.
+const MIN_UDP_PAYLOAD: u16 = 512;
+
+// Maximum number of records sent in each message of a zone transfer.
+const AXFR_MESSAGE_RECORDS: usize = 100;
+
+// Maximum number of CNAMEs followed when answering a query for an alias.
+const MAX_CNAME_CHAIN: usize = 8;
 
 impl Handler {
-    pub(super) fn new(config: Shared, txt_store: DynTxtStore) -> Self {
-        let txt_domain_set = Self::txt_domain_set(&config);
-        Handler {
+    // Construct a handler, loading the DNSSEC signing keys if configured.
+    #[cfg_attr(not(feature = "dnssec"), allow(clippy::unnecessary_wraps))]
+    pub(super) fn new(
+        config: DynConfig,
+        txt_store: DynTxtStore,
+        notifier: Notifier,
+        drain: Drain,
+    ) -> Result<Self, Error> {
+        Ok(Handler {
+            #[cfg(feature = "dnssec")]
+            signer: ZoneSigner::from_config(&config.current())?,
             config,
-            txt_domain_set,
             txt_store,
-        }
+            notifier,
+            drain,
+            rate_limiter: Arc::default(),
+        })
     }
 
-    fn txt_domain_set(config: &Shared) -> HashSet<LowerName> {
-        // Build a HashSet of all of the names that appear in the allowed network configs. We use
-        // this to quickly determine whether to return NXDOMAIN for a TXT lookup.
-        let mut txt_domain_set: HashSet<LowerName> = HashSet::default();
-        for network_allowed_set in config.acl.values() {
-            for subdomain in network_allowed_set {
-                let subdomain: Name = subdomain.into();
-                let fqdn = subdomain.append_domain(&(&config.domain).into()).unwrap();
-                txt_domain_set.insert(fqdn.clone().into());
-            }
-        }
-        txt_domain_set
+    // Returns the DS record of each zone for its parent zone if DNSSEC signing is enabled.
+    #[cfg(feature = "dnssec")]
+    pub(super) fn ds(&self) -> Result<Vec<String>, Error> {
+        let Some(signer) = &self.signer else {
+            return Ok(Vec::default());
+        };
+        self.config
+            .current()
+            .zones
+            .iter()
+            .map(|zone| signer.ds(&zone.domain))
+            .collect()
+    }
+
+    // Returns the signer if DNSSEC signing is enabled and the request set the DNSSEC OK bit.
+    #[cfg(feature = "dnssec")]
+    fn signer_for(&self, request: &Request) -> Option<&ZoneSigner> {
+        self.signer
+            .as_deref()
+            .filter(|_| request.edns().map_or(false, Edns::dnssec_ok))
     }
 
     async fn dispatch_request<R: ResponseHandler>(
         &self,
         request: &Request,
+        message: &[u8],
         response: R,
     ) -> Result<ResponseInfo, Error> {
-        // If it isn't a query, return NOTIMPL.
-        if request.op_code() != OpCode::Query || request.message_type() != MessageType::Query {
-            return self.handle_notimpl(request, response).await;
-        }
+        // Use the same config for the whole request, even if it is reloaded concurrently.
+        let config = &self.config.current();
 
-        // Otherwise handle by query type, or return NOTIMPL.
-        match request.query().query_type() {
-            RecordType::TXT => self.handle_request_txt(request, response).await,
-            RecordType::SOA => self.handle_request_soa(request, response).await,
-            RecordType::A => self.handle_request_a(request, response).await,
-            RecordType::AAAA => self.handle_request_aaaa(request, response).await,
-            RecordType::NS => self.handle_request_ns(request, response).await,
-            _ => self.handle_notimpl(request, response).await,
-        }
+        let query_type = request.query().query_type();
+        let result = if request
+            .edns()
+            .map_or(false, |edns| edns.version() > SUPPORTED_EDNS_VERSION)
+        {
+            // If it uses an EDNS version we don't support, return BADVERS.
+            self.send_error(config, request, response, ResponseCode::BADVERS)
+                .await
+        } else if request.op_code() == OpCode::Update
+            && request.message_type() == MessageType::Query
+        {
+            // If it is an UPDATE, check it is signed by a TSIG key allowed to make the changes.
+            update::handle_update(
+                config,
+                &self.txt_store,
+                &self.notifier,
+                request,
+                message,
+                response,
+            )
+            .await
+        } else if request.op_code() != OpCode::Query || request.message_type() != MessageType::Query
+        {
+            // If it isn't a query, return NOTIMPL.
+            self.handle_notimpl(config, request, response).await
+        } else if let Some(zone) = config.zone(request.query().name()) {
+            // Otherwise handle queries for names in a zone by query type.
+            let name = request.query().name();
+            let is_alias = zone.cnames.contains_key(name);
+            match query_type {
+                RecordType::CNAME => {
+                    self.handle_request_cname(config, zone, request, response)
+                        .await
+                }
+                RecordType::AXFR => {
+                    self.handle_request_axfr(config, zone, request, response)
+                        .await
+                }
+                #[cfg(feature = "dnssec")]
+                RecordType::DNSKEY if self.signer.is_some() => {
+                    self.handle_request_dnskey(config, zone, request, response)
+                        .await
+                }
+                _ if is_alias => {
+                    self.handle_request_alias(config, zone, request, response)
+                        .await
+                }
+                RecordType::TXT => {
+                    self.handle_request_txt(config, zone, request, response)
+                        .await
+                }
+                RecordType::SOA => {
+                    self.handle_request_soa(config, zone, request, response)
+                        .await
+                }
+                RecordType::A => self.handle_request_a(config, zone, request, response).await,
+                RecordType::AAAA => {
+                    self.handle_request_aaaa(config, zone, request, response)
+                        .await
+                }
+                RecordType::NS => {
+                    self.handle_request_ns(config, zone, request, response)
+                        .await
+                }
+                RecordType::CAA => {
+                    self.handle_request_caa(config, zone, request, response)
+                        .await
+                }
+                // The zone has no records of any other type, so answer NODATA, or NXDOMAIN if the
+                // name doesn't exist.
+                _ => {
+                    self.send_auth_resp(config, zone, request, response, Vec::default())
+                        .await
+                }
+            }
+        } else {
+            // Queries for names outside of every zone aren't ours to answer, so return REFUSED
+            // rather than an authoritative NXDOMAIN.
+            self.handle_refused(config, request, response).await
+        };
+
+        // Errors are answered with SERVFAIL by handle_request.
+        let rcode = result
+            .as_ref()
+            .map_or(ResponseCode::ServFail, |info| info.response_code());
+        metrics::dns_query(query_type, rcode);
+        result
+    }
+
+    async fn handle_refused<R: ResponseHandler>(
+        &self,
+        config: &Config,
+        request: &Request,
+        response_handle: R,
+    ) -> Result<ResponseInfo, Error> {
+        self.send_error(config, request, response_handle, ResponseCode::Refused)
+            .await
     }
 
     async fn handle_notimpl<R: ResponseHandler>(
         &self,
+        config: &Config,
         request: &Request,
-        mut response_handle: R,
+        response_handle: R,
     ) -> Result<ResponseInfo, Error> {
-        let response = MessageResponseBuilder::from_message_request(request);
-        Ok(response_handle
-            .send_response(response.error_msg(request.header(), ResponseCode::NotImp))
-            .await?)
+        self.send_error(config, request, response_handle, ResponseCode::NotImp)
+            .await
     }
 
     async fn handle_request_txt<R: ResponseHandler>(
         &self,
+        config: &Config,
+        zone: &Zone,
         request: &Request,
         response_handle: R,
     ) -> Result<ResponseInfo, Error> {
-        let query_name = request.query().name();
-        if self.txt_domain_set.get(query_name).is_none() {
-            return self.send_nxdomain(request, response_handle).await;
-        }
-
-        let txt_data = self.txt_rdata(query_name).await;
-        self.send_auth_resp(request, response_handle, txt_data)
+        let fqdn = request.query().name();
+        let txt_data = self.txt_rdata(zone, fqdn).await?;
+        self.send_auth_resp(config, zone, request, response_handle, txt_data)
             .await
     }
 
     async fn handle_request_soa<R: ResponseHandler>(
         &self,
+        config: &Config,
+        zone: &Zone,
         request: &Request,
         response_handle: R,
     ) -> Result<ResponseInfo, Error> {
-        let query_name = request.query().name();
-        if *query_name != self.config.domain {
-            return self.send_nxdomain(request, response_handle).await;
-        }
-
-        // NB: unwraps are safe: known date format producing values that will always parse as u32.
-        let serial: u32 = OffsetDateTime::now_utc()
-            .format(&SERIAL_FORMATTER)
-            .unwrap()
-            .parse()
-            .unwrap();
-        let ns_admin = self.config.ns_admin()?;
-        // See RIPE 203[0] for recommended values.
-        // [0]: https://www.ripe.net/publications/docs/ripe-203
-        let soa_rdata = RData::SOA(SOA::new(
-            self.config.ns_domain.clone().into(),
-            ns_admin,
-            serial,
-            86_400,    // 24 hrs.
-            7_200,     // 2 hours.
-            3_600_000, // 1000 hours.
-            172_800,   // 2 days.
-        ));
-        self.send_auth_resp(request, response_handle, vec![soa_rdata])
+        let fqdn = request.query().name();
+        let soa_data = self.soa_rdata(config, zone, fqdn).await?;
+        self.send_auth_resp(config, zone, request, response_handle, soa_data)
             .await
     }
 
     async fn handle_request_a<R: ResponseHandler>(
         &self,
+        config: &Config,
+        zone: &Zone,
         request: &Request,
         response_handle: R,
     ) -> Result<ResponseInfo, Error> {
         let fqdn = request.query().name();
-        match self.config.addrs.get(fqdn) {
-            None => self.send_nxdomain(request, response_handle).await,
-            Some(_) => {
-                self.send_auth_resp(request, response_handle, self.a_rdata(fqdn))
-                    .await
-            }
-        }
+        self.send_auth_resp(
+            config,
+            zone,
+            request,
+            response_handle,
+            Self::a_rdata(zone, fqdn),
+        )
+        .await
     }
 
     async fn handle_request_aaaa<R: ResponseHandler>(
         &self,
+        config: &Config,
+        zone: &Zone,
         request: &Request,
         response_handle: R,
     ) -> Result<ResponseInfo, Error> {
         let fqdn = request.query().name();
-        match self.config.addrs.get(fqdn) {
-            None => self.send_nxdomain(request, response_handle).await,
-            Some(_) => {
-                self.send_auth_resp(request, response_handle, self.aaaa_rdata(fqdn))
-                    .await
+        self.send_auth_resp(
+            config,
+            zone,
+            request,
+            response_handle,
+            Self::aaaa_rdata(zone, fqdn),
+        )
+        .await
+    }
+
+    async fn handle_request_ns<R: ResponseHandler>(
+        &self,
+        config: &Config,
+        zone: &Zone,
+        request: &Request,
+        response_handle: R,
+    ) -> Result<ResponseInfo, Error> {
+        let fqdn = request.query().name();
+        self.send_auth_resp(
+            config,
+            zone,
+            request,
+            response_handle,
+            Self::ns_rdata(zone, fqdn),
+        )
+        .await
+    }
+
+    async fn handle_request_caa<R: ResponseHandler>(
+        &self,
+        config: &Config,
+        zone: &Zone,
+        request: &Request,
+        response_handle: R,
+    ) -> Result<ResponseInfo, Error> {
+        let fqdn = request.query().name();
+        self.send_auth_resp(
+            config,
+            zone,
+            request,
+            response_handle,
+            Self::caa_rdata(zone, fqdn),
+        )
+        .await
+    }
+
+    // Answer a DNSKEY query with the signing keys at the zone apex.
+    #[cfg(feature = "dnssec")]
+    async fn handle_request_dnskey<R: ResponseHandler>(
+        &self,
+        config: &Config,
+        zone: &Zone,
+        request: &Request,
+        response_handle: R,
+    ) -> Result<ResponseInfo, Error> {
+        let rdata = match &self.signer {
+            Some(signer) if zone.domain == *request.query().name() => signer.dnskey_rdata(),
+            _ => Vec::default(),
+        };
+        self.send_auth_resp(config, zone, request, response_handle, rdata)
+            .await
+    }
+
+    // Answer a zone transfer request with every record in the zone, starting and ending with the
+    // SOA. Transfers are only allowed over TCP, for the zone apex, from the transfer allowed
+    // networks.
+    async fn handle_request_axfr<R: ResponseHandler>(
+        &self,
+        config: &Config,
+        zone: &Zone,
+        request: &Request,
+        mut response_handle: R,
+    ) -> Result<ResponseInfo, Error> {
+        let src = request.src().ip();
+        if !matches!(request.protocol(), Protocol::Tcp) || !config.transfer_permitted(src) {
+            tracing::debug!(
+                "refused zone transfer from {src} over {}",
+                request.protocol()
+            );
+            return self.handle_refused(config, request, response_handle).await;
+        }
+        if zone.domain != *request.query().name() {
+            return self
+                .send_error(config, request, response_handle, ResponseCode::NotAuth)
+                .await;
+        }
+
+        let records = self.zone_records(config, zone).await?;
+        tracing::info!("zone transfer of {} records to {src}", records.len());
+        let mut info = None;
+        for chunk in records.chunks(AXFR_MESSAGE_RECORDS) {
+            let mut header = Header::response_from_request(request.header());
+            header.set_authoritative(true);
+            let mut builder = MessageResponseBuilder::from_message_request(request);
+            if let Some(edns) = Self::response_edns(config, request) {
+                builder.edns(edns);
             }
+            let response = builder.build(header, chunk.iter(), &[], &[], &[]);
+            info = Some(response_handle.send_response(response).await?);
         }
+        // NB: the zone records always include the SOA, so at least one message was sent.
+        Ok(info.expect("zone transfer sent no messages"))
     }
 
-    async fn handle_request_ns<R: ResponseHandler>(
+    async fn handle_request_cname<R: ResponseHandler>(
         &self,
+        config: &Config,
+        zone: &Zone,
         request: &Request,
         response_handle: R,
     ) -> Result<ResponseInfo, Error> {
         let fqdn = request.query().name();
-        match self.config.ns_records.get(fqdn) {
-            None => self.send_nxdomain(request, response_handle).await,
-            Some(_) => {
-                self.send_auth_resp(request, response_handle, self.ns_rdata(fqdn))
-                    .await
+        self.send_auth_resp(
+            config,
+            zone,
+            request,
+            response_handle,
+            Self::cname_rdata(zone, fqdn),
+        )
+        .await
+    }
+
+    // Answer a query for a CNAME alias. In-zone CNAMEs are chased, returning each CNAME in the
+    // chain followed by the records of the queried type for the final target (if any).
+    async fn handle_request_alias<R: ResponseHandler>(
+        &self,
+        config: &Config,
+        zone: &Zone,
+        request: &Request,
+        response_handle: R,
+    ) -> Result<ResponseInfo, Error> {
+        let mut answers = Vec::default();
+        let mut seen = HashSet::new();
+        let mut name = request.query().name().clone();
+        while let Some(target) = zone.cnames.get(&name) {
+            if !seen.insert(name.clone()) || answers.len() >= MAX_CNAME_CHAIN {
+                tracing::warn!("CNAME chain for \"{}\" is too long", request.query().name());
+                break;
             }
+            answers.push(Self::record(zone, name.into(), RData::CNAME(target.into())));
+            name = target.clone();
         }
+
+        // Targets outside of the zone are left to the resolver to chase.
+        if !zone.domain.zone_of(&name) {
+            return self
+                .send_answers(config, zone, request, response_handle, &name, answers, true)
+                .await;
+        }
+
+        let rdata = self
+            .rdata(config, zone, &name, request.query().query_type())
+            .await?;
+        let found = !rdata.is_empty();
+        answers.extend(
+            rdata
+                .into_iter()
+                .map(|rd| Self::record(zone, (&name).into(), rd)),
+        );
+        self.send_answers(
+            config,
+            zone,
+            request,
+            response_handle,
+            &name,
+            answers,
+            found,
+        )
+        .await
     }
 
-    async fn txt_rdata(&self, key: &LowerName) -> Vec<RData> {
+    // Returns every record in the zone for a zone transfer, starting and ending with the SOA.
+    async fn zone_records(&self, config: &Config, zone: &Zone) -> Result<Vec<Record>, Error> {
+        let soa = Self::record(
+            zone,
+            zone.domain.clone().into(),
+            RData::SOA(Self::soa(
+                zone,
+                Self::serial(config, &self.txt_store).await?,
+            )?),
+        );
+        let mut records = vec![soa.clone()];
+
+        // NB: names within a more specific zone belong to that zone instead.
+        let in_zone = |name: &LowerName| {
+            config
+                .zone(name)
+                .map_or(false, |name_zone| name_zone.domain == zone.domain)
+        };
+        let mut names: Vec<&LowerName> = zone
+            .ns_records
+            .keys()
+            .chain(zone.addrs.keys())
+            .chain(zone.cnames.keys())
+            .chain(zone.caa_records.keys())
+            .filter(|name| in_zone(name))
+            .collect();
+        names.sort();
+        names.dedup();
+        for name in names {
+            let rdata = Self::ns_rdata(zone, name)
+                .into_iter()
+                .chain(Self::a_rdata(zone, name))
+                .chain(Self::aaaa_rdata(zone, name))
+                .chain(Self::cname_rdata(zone, name))
+                .chain(Self::caa_rdata(zone, name));
+            records.extend(rdata.map(|rd| Self::record(zone, name.into(), rd)));
+        }
+
+        let txt_store = self.txt_store.read().await;
+        for (fqdn, values) in txt_store.all_txt().await? {
+            if !in_zone(&fqdn)
+                || (!zone.is_txt_domain(&fqdn) && !txt_store.account_exists(&fqdn).await?)
+            {
+                continue;
+            }
+            records.extend(values.into_iter().map(|value| {
+                Self::record(zone, (&fqdn).into(), RData::TXT(TXT::new(vec![value])))
+            }));
+        }
+
+        records.push(soa);
+        Ok(records)
+    }
+
+    // Returns every name in the zone with the types of its records, for the NSEC records of a
+    // negative answer for name. ACL subdomains without TXT records are included since they exist,
+    // as is the name itself if it exists, e.g. an account subdomain without TXT records.
+    #[cfg(feature = "dnssec")]
+    async fn nsec_chain(
+        &self,
+        config: &Config,
+        zone: &Zone,
+        name: &LowerName,
+        exists: bool,
+    ) -> Result<NsecChain, Error> {
+        let mut chain = NsecChain::new();
+        for record in self.zone_records(config, zone).await? {
+            chain
+                .entry(record.name().into())
+                .or_default()
+                .insert(record.rr_type());
+        }
+        chain
+            .entry(zone.domain.clone())
+            .or_default()
+            .insert(RecordType::DNSKEY);
+        for txt_name in zone.txt_domain_names() {
+            if zone.domain.zone_of(txt_name) {
+                chain.entry(txt_name.clone()).or_default();
+            }
+        }
+        // NB: empty non-terminals are left out, they are found as ancestors of the names below.
+        if exists && !chain.keys().any(|owner| name.zone_of(owner)) {
+            chain.entry(name.clone()).or_default();
+        }
+        Ok(chain)
+    }
+
+    // Returns the records of the given type for fqdn, for the query types that can be chased
+    // through a CNAME.
+    async fn rdata(
+        &self,
+        config: &Config,
+        zone: &Zone,
+        fqdn: &LowerName,
+        query_type: RecordType,
+    ) -> Result<Vec<RData>, Error> {
+        Ok(match query_type {
+            RecordType::TXT => self.txt_rdata(zone, fqdn).await?,
+            RecordType::SOA => self.soa_rdata(config, zone, fqdn).await?,
+            RecordType::A => Self::a_rdata(zone, fqdn),
+            RecordType::AAAA => Self::aaaa_rdata(zone, fqdn),
+            RecordType::NS => Self::ns_rdata(zone, fqdn),
+            RecordType::CAA => Self::caa_rdata(zone, fqdn),
+            _ => Vec::default(),
+        })
+    }
+
+    // TXT values are only served for ACL subdomains and registered account subdomains.
+    async fn txt_rdata(&self, zone: &Zone, key: &LowerName) -> Result<Vec<RData>, Error> {
         let read_store = self.txt_store.read().await;
-        let records = read_store.get_txt(key).await;
-        records
+        if !zone.is_txt_domain(key) && !read_store.account_exists(key).await? {
+            return Ok(Vec::default());
+        }
+        let records = read_store.get_txt(key).await?;
+        Ok(records
+            .into_iter()
+            .map(|x| RData::TXT(TXT::new(vec![x])))
+            .collect())
+    }
+
+    async fn soa_rdata(
+        &self,
+        config: &Config,
+        zone: &Zone,
+        fqdn: &LowerName,
+    ) -> Result<Vec<RData>, Error> {
+        if *fqdn == zone.domain {
+            Ok(vec![RData::SOA(Self::soa(
+                zone,
+                Self::serial(config, &self.txt_store).await?,
+            )?)])
+        } else {
+            Ok(Vec::default())
+        }
+    }
+
+    fn caa_rdata(zone: &Zone, fqdn: &LowerName) -> Vec<RData> {
+        zone.caa(fqdn)
             .iter()
-            .flatten()
-            .map(|x| RData::TXT(TXT::new(vec![(*x).to_string()])))
+            .map(|caa| RData::CAA(caa.clone()))
             .collect()
     }
 
-    fn addrs_from_config(&self, fqdn: &LowerName) -> Vec<IpAddr> {
-        self.config
-            .addrs
+    fn cname_rdata(zone: &Zone, fqdn: &LowerName) -> Vec<RData> {
+        zone.cnames
             .get(fqdn)
-            .map_or(Vec::default(), Clone::clone)
+            .map(|target| RData::CNAME(target.into()))
+            .into_iter()
+            .collect()
     }
 
-    fn ns_names_from_config(&self, fqdn: &LowerName) -> Vec<LowerName> {
-        self.config
-            .ns_records
+    fn addrs_from_config(zone: &Zone, fqdn: &LowerName) -> Vec<IpAddr> {
+        zone.addrs.get(fqdn).map_or(Vec::default(), Clone::clone)
+    }
+
+    fn ns_names_from_config(zone: &Zone, fqdn: &LowerName) -> Vec<LowerName> {
+        zone.ns_records
             .get(fqdn)
             .map_or(Vec::default(), Clone::clone)
     }
 
-    fn a_rdata(&self, fqdn: &LowerName) -> Vec<RData> {
-        self.addrs_from_config(fqdn)
+    fn a_rdata(zone: &Zone, fqdn: &LowerName) -> Vec<RData> {
+        Self::addrs_from_config(zone, fqdn)
             .iter()
             .filter_map(|ip| match ip {
                 IpAddr::V4(ipv4_addr) => Some(RData::A(*ipv4_addr)),
@@ -208,8 +597,8 @@ impl Handler {
             .collect()
     }
 
-    fn aaaa_rdata(&self, fqdn: &LowerName) -> Vec<RData> {
-        self.addrs_from_config(fqdn)
+    fn aaaa_rdata(zone: &Zone, fqdn: &LowerName) -> Vec<RData> {
+        Self::addrs_from_config(zone, fqdn)
             .iter()
             .filter_map(|ip| match ip {
                 IpAddr::V4(_) => None,
@@ -218,55 +607,272 @@ impl Handler {
             .collect()
     }
 
-    fn ns_rdata(&self, fqdn: &LowerName) -> Vec<RData> {
-        self.ns_names_from_config(fqdn)
+    fn ns_rdata(zone: &Zone, fqdn: &LowerName) -> Vec<RData> {
+        Self::ns_names_from_config(zone, fqdn)
             .iter()
             .map(|n| RData::NS(n.into()))
             .collect()
     }
 
+    // Returns the zone serial using the configured scheme.
+    pub(super) async fn serial(config: &Config, txt_store: &DynTxtStore) -> Result<u32, Error> {
+        match config.soa_serial {
+            SoaSerial::Counter => txt_store.read().await.serial().await,
+            SoaSerial::Date => Ok(date_serial()),
+        }
+    }
+
+    pub(super) fn soa(zone: &Zone, serial: u32) -> Result<SOA, Error> {
+        let ns_admin = zone.ns_admin()?;
+        let timers = &zone.soa_timers;
+        // NB: the config ensures timers are at most 2^31 - 1, so always fit an i32.
+        let timer = |secs: u32| i32::try_from(secs).unwrap_or(i32::MAX);
+        Ok(SOA::new(
+            zone.ns_domain.clone().into(),
+            ns_admin,
+            serial,
+            timer(timers.refresh),
+            timer(timers.retry),
+            timer(timers.expire),
+            timers.minimum,
+        ))
+    }
+
+    // Returns a record with the zone's TTL for its type.
+    fn record(zone: &Zone, name: Name, rdata: RData) -> Record {
+        Record::from_rdata(name, zone.ttls.ttl(rdata.to_record_type()), rdata)
+    }
+
+    // Returns true if the name exists in the zone: it is the zone apex, has static records,
+    // is (or matches a pattern for) an ACL or registered TXT subdomain, or is an empty
+    // non-terminal above any of these.
+    async fn name_exists(&self, zone: &Zone, name: &LowerName) -> Result<bool, Error> {
+        if !zone.domain.zone_of(name) {
+            return Ok(false);
+        }
+        let static_names = zone
+            .addrs
+            .keys()
+            .chain(zone.ns_records.keys())
+            .chain(zone.cnames.keys())
+            .chain(zone.txt_domain_names());
+        for static_name in static_names {
+            if name.zone_of(static_name) {
+                return Ok(true);
+            }
+        }
+        Ok(*name == zone.domain
+            || zone.is_txt_domain(name)
+            || self.txt_store.read().await.account_exists(name).await?)
+    }
+
     async fn send_auth_resp<R: ResponseHandler>(
         &self,
+        config: &Config,
+        zone: &Zone,
         request: &Request,
-        mut response_handle: R,
+        response_handle: R,
         rdata: Vec<RData>,
     ) -> Result<ResponseInfo, Error> {
+        let query_name = request.query().name();
+        let found = !rdata.is_empty();
         let records: Vec<Record> = rdata
-            .iter()
-            .map(|rd| Record::from_rdata(request.query().name().into(), 1, rd.clone()))
+            .into_iter()
+            .map(|rd| Self::record(zone, query_name.into(), rd))
             .collect();
+        self.send_answers(
+            config,
+            zone,
+            request,
+            response_handle,
+            query_name,
+            records,
+            found,
+        )
+        .await
+    }
+
+    // Send an authoritative response with the given answers. If no records were found for the
+    // name the query resolved to, a negative answer per RFC 2308 is sent: NODATA (NOERROR) if the
+    // name exists, and NXDOMAIN otherwise. For names in the zone the SOA is included in the
+    // authority section so resolvers can cache the negative answer.
+    #[allow(clippy::too_many_arguments)]
+    async fn send_answers<R: ResponseHandler>(
+        &self,
+        config: &Config,
+        zone: &Zone,
+        request: &Request,
+        mut response_handle: R,
+        name: &LowerName,
+        mut answers: Vec<Record>,
+        found: bool,
+    ) -> Result<ResponseInfo, Error> {
+        let mut rcode = ResponseCode::NoError;
+        let mut soa_records = Vec::default();
+        if !found {
+            if !self.name_exists(zone, name).await? {
+                rcode = ResponseCode::NXDomain;
+            }
+            if zone.domain.zone_of(name) {
+                let soa = Self::soa(zone, Self::serial(config, &self.txt_store).await?)?;
+                // The negative caching TTL is the lesser of the SOA TTL and its minimum field.
+                let ttl = zone.ttls.soa.min(soa.minimum());
+                soa_records.push(Record::from_rdata(
+                    zone.domain.clone().into(),
+                    ttl,
+                    RData::SOA(soa),
+                ));
+            }
+        }
+
         let mut header = Header::response_from_request(request.header());
         header.set_authoritative(true);
-        let builder = MessageResponseBuilder::from_message_request(request);
-        let response = builder.build(header, records.iter(), &[], &[], &[]);
+        header.set_response_code(rcode);
+
+        let kind = match rcode {
+            ResponseCode::NXDomain => ResponseKind::NxDomain,
+            _ => ResponseKind::Answer,
+        };
+        let slip = match self.rate_limiter.check(config, request, kind) {
+            RateLimitAction::Send => false,
+            RateLimitAction::Slip => true,
+            RateLimitAction::Drop => return Ok(header.into()),
+        };
+
+        #[cfg_attr(not(feature = "dnssec"), allow(unused_mut))]
+        let mut edns = Self::response_edns(config, request);
+        // Sign the response for clients that set the DNSSEC OK bit, proving the absence of
+        // records with NSEC records for negative answers in the zone.
+        #[cfg(feature = "dnssec")]
+        if let Some(signer) = self.signer_for(request).filter(|_| !slip) {
+            if let Some(soa) = soa_records.first() {
+                let nxdomain = rcode == ResponseCode::NXDomain;
+                // The NSEC TTL is the negative caching TTL, like the SOA.
+                let ttl = soa.ttl();
+                let chain = self.nsec_chain(config, zone, name, !nxdomain).await?;
+                soa_records.extend(ZoneSigner::denial(&chain, name, nxdomain, ttl)?);
+            }
+            answers.extend(signer.sign(&zone.domain, &answers)?);
+            soa_records.extend(signer.sign(&zone.domain, &soa_records)?);
+            if let Some(edns) = edns.as_mut() {
+                edns.set_dnssec_ok(true);
+            }
+        }
+
+        // If the response doesn't fit in the client's UDP payload size, or is slipped by rate
+        // limiting, send it truncated so the client retries over TCP.
+        let max_size = Self::max_response_size(config, request);
+        if slip
+            || Self::encoded_len(request, header, &answers, &soa_records, edns.as_ref())? > max_size
+        {
+            header.set_truncated(true);
+            answers.clear();
+            soa_records.clear();
+        }
+
+        let mut builder = MessageResponseBuilder::from_message_request(request);
+        if let Some(edns) = edns {
+            builder.edns(edns);
+        }
+        let response = builder.build(header, answers.iter(), &[], soa_records.iter(), &[]);
         Ok(response_handle.send_response(response).await?)
     }
 
-    async fn send_nxdomain<R: ResponseHandler>(
+    async fn send_error<R: ResponseHandler>(
         &self,
+        config: &Config,
         request: &Request,
         mut response_handle: R,
+        rcode: ResponseCode,
     ) -> Result<ResponseInfo, Error> {
-        let builder = MessageResponseBuilder::from_message_request(request);
         let mut header = Header::response_from_request(request.header());
-        header.set_authoritative(true);
-        header.set_response_code(ResponseCode::NXDomain);
-        let response = builder.build_no_records(header);
-        Ok(response_handle.send_response(response).await?)
+        header.set_response_code(rcode);
+        match self
+            .rate_limiter
+            .check(config, request, ResponseKind::Error)
+        {
+            RateLimitAction::Send => {}
+            RateLimitAction::Slip => {
+                header.set_truncated(true);
+            }
+            RateLimitAction::Drop => return Ok(header.into()),
+        }
+
+        let mut builder = MessageResponseBuilder::from_message_request(request);
+        if let Some(edns) = Self::response_edns(config, request) {
+            builder.edns(edns);
+        }
+        Ok(response_handle
+            .send_response(builder.build_no_records(header))
+            .await?)
+    }
+
+    // Returns the OPT record to include in the response, echoing EDNS(0) when the request used
+    // EDNS. The advertised payload size is the server's configured maximum.
+    fn response_edns(config: &Config, request: &Request) -> Option<Edns> {
+        request.edns()?;
+        let mut edns = Edns::new();
+        edns.set_version(SUPPORTED_EDNS_VERSION);
+        edns.set_max_payload(config.dns_max_udp_payload);
+        Some(edns)
+    }
+
+    // Returns the maximum size of a response to the request. Responses over TCP are only limited
+    // by the message length prefix. Over UDP responses are limited to 512 bytes, or the payload
+    // size advertised by an EDNS client, capped by the server's configured maximum.
+    fn max_response_size(config: &Config, request: &Request) -> usize {
+        if !matches!(request.protocol(), Protocol::Udp) {
+            return usize::from(u16::MAX);
+        }
+        let max_payload = request.edns().map_or(MIN_UDP_PAYLOAD, |edns| {
+            edns.max_payload().min(config.dns_max_udp_payload)
+        });
+        usize::from(max_payload.max(MIN_UDP_PAYLOAD))
+    }
+
+    fn encoded_len(
+        request: &Request,
+        header: Header,
+        answers: &[Record],
+        soa_records: &[Record],
+        edns: Option<&Edns>,
+    ) -> Result<usize, Error> {
+        let mut message = Message::new();
+        message.set_header(header);
+        message.add_query(request.query().original().clone());
+        message.add_answers(answers.iter().cloned());
+        message.add_name_servers(soa_records.iter().cloned());
+        if let Some(edns) = edns {
+            message.set_edns(edns.clone());
+        }
+        Ok(message.to_vec()?.len())
     }
 }
 
-#[async_trait::async_trait]
-impl RequestHandler for Handler {
-    async fn handle_request<R: ResponseHandler>(
+impl Handler {
+    // Answer the request, decoded from the message bytes, with the response handle. The message
+    // bytes are kept to verify the TSIG of UPDATE requests. Errors are answered with SERVFAIL.
+    pub(super) async fn handle_request<R: ResponseHandler>(
         &self,
         request: &Request,
+        message: &[u8],
         response_handle: R,
     ) -> ResponseInfo {
-        match self.dispatch_request(request, response_handle).await {
+        // Refuse new requests once shutdown has started draining in-flight requests.
+        let result = match self.drain.track() {
+            Some(_in_flight) => {
+                self.dispatch_request(request, message, response_handle)
+                    .await
+            }
+            None => {
+                self.handle_refused(&self.config.current(), request, response_handle)
+                    .await
+            }
+        };
+        match result {
             Ok(info) => info,
             Err(error) => {
-                error!("error in RequestHandler: {:?}", error);
+                error!("error handling DNS request: {:?}", error);
                 let mut header = Header::new();
                 header.set_response_code(ResponseCode::ServFail);
                 header.into()
@@ -274,3 +880,74 @@ impl RequestHandler for Handler {
         }
     }
 }
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use crate::dns::DohHandler;
+    use crate::txt_store::memory::InMemoryTxtStore;
+    use serde_json::json;
+    use std::str::FromStr;
+    use tokio::sync::RwLock;
+    use trust_dns_server::client::op::Query;
+
+    // Constructs a handler serving the pki.example.com zone from an in-memory TXT store.
+    pub(in crate::dns) fn handler() -> Arc<Handler> {
+        let config = json!({
+            "domain": "pki.example.com",
+            "ns_domain": "ns1.pki.example.com",
+            "ns_admin": "dns-admin@example.com",
+            "api_bind_addr": "127.0.0.1:3000",
+            "api_timeout": 120,
+            "dns_udp_bind_addr": "127.0.0.1:5353",
+            "dns_tcp_bind_addr": "127.0.0.1:5353",
+            "dns_tcp_timeout": 60,
+            "acl": { "127.0.0.1/32": [ "test" ] },
+            "addrs": { "pki.example.com": [ "93.184.216.34" ] },
+            "ns_records": { "pki.example.com": [ "ns1.pki.example.com" ] }
+        });
+        let path = std::env::temp_dir().join(format!("acmecrab-{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(&path, config.to_string()).unwrap();
+        let config = DynConfig::try_from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        let config = config.unwrap();
+
+        let txt_store: DynTxtStore = Arc::new(RwLock::new(InMemoryTxtStore::new(None)));
+        let notifier = Notifier::new(config.clone(), txt_store.clone());
+        Arc::new(Handler::new(config, txt_store, notifier, Drain::default()).unwrap())
+    }
+
+    // Sends a query for the name and type, returning the response.
+    async fn query(handler: &DohHandler, name: &str, query_type: RecordType) -> Message {
+        let mut message = Message::new();
+        message
+            .set_id(1234)
+            .set_recursion_desired(false)
+            .add_query(Query::query(Name::from_str(name).unwrap(), query_type));
+        let response = handler
+            .answer(
+                &message.to_vec().unwrap(),
+                "127.0.0.1:53000".parse().unwrap(),
+            )
+            .await
+            .unwrap();
+        Message::from_vec(&response.message).unwrap()
+    }
+
+    #[tokio::test]
+    async fn unserved_types_in_zone_are_negative_answers() {
+        let handler = DohHandler::new(handler());
+
+        let response = query(&handler, "pki.example.com.", RecordType::MX).await;
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert!(response.authoritative());
+        assert!(response.answers().is_empty());
+        assert_eq!(response.name_servers().len(), 1);
+        assert_eq!(response.name_servers()[0].record_type(), RecordType::SOA);
+
+        let response = query(&handler, "missing.pki.example.com.", RecordType::MX).await;
+        assert_eq!(response.response_code(), ResponseCode::NXDomain);
+        assert!(response.answers().is_empty());
+        assert_eq!(response.name_servers()[0].record_type(), RecordType::SOA);
+    }
+}
@@ -2,10 +2,11 @@
 //!
 //! # Dynamic TXT Records
 //!
-//! ACME Crab will serve a response to `TXT` class queries for subdomains of the configured
-//! [`Config::domain`][`crate::config::Config::domain`], iff a [RFC-8555][RFC-8555] [DNS-01]
+//! ACME Crab will serve a response to `TXT` class queries for subdomains of each configured
+//! [`Zone::domain`][`crate::config::Zone::domain`], iff a [RFC-8555][RFC-8555] [DNS-01]
 //! challenge response value has been provisioned by a client `POST`ing the
-//! [`/update` API endpoint][crate::api#update-post].
+//! [`/update` API endpoint][crate::api#update-post]. Subdomains created by the
+//! [`/register` API endpoint][crate::api#register-post] are served the same way.
 //!
 //! E.g. with config:
 //! ```json
@@ -36,6 +37,28 @@
 //! [RFC-8555]: https://www.rfc-editor.org/rfc/rfc8555
 //! [DNS-01]: https://www.rfc-editor.org/rfc/rfc8555#section-8.4
 //!
+//! # Multiple Zones
+//!
+//! One instance can be authoritative for several [`Config::zones`][`crate::config::Config::zones`],
+//! each with its own SOA settings, ACL, static records and TTLs. Queries are answered from the
+//! zone with the longest [`Zone::domain`][`crate::config::Zone::domain`] the query name is
+//! within, so a zone may be nested inside another. The zone keys may also be given at the top
+//! level of the config, and are then loaded as the first zone.
+//!
+//! E.g. with config:
+//! ```json
+//! {
+//!   "zones": [
+//!     { "domain": "pki.example.com", "acl": { "127.0.0.1/32": [ "test" ] }, ... },
+//!     { "domain": "lab.example.org", "acl": { "10.0.0.0/8": [ "test" ] }, ... }
+//!   ],
+//!   ...
+//! }
+//! ```
+//!
+//! Then `test.pki.example.com` and `test.lab.example.org` are separate names, updated by `POST`s
+//! to the [`/update` API endpoint][crate::api#update-post] that name their `zone`.
+//!
 //! # Static Records
 //!
 //! Several record types are served based on the static [Config][`crate::config::Config`] used
@@ -45,7 +68,7 @@
 //! # A/AAAA
 //!
 //! ACME Crab will serve a response to `A` or `AAAA` class queries for each FQDN in the config
-//! [`Config::addrs`][`crate::config::Config::addrs`] map. Only IPv4 values will be used for `A`
+//! [`Zone::addrs`][`crate::config::Zone::addrs`] map. Only IPv4 values will be used for `A`
 //! class queries, and IPv6 values for `AAAA`.
 //!
 //! E.g. with config:
@@ -72,7 +95,7 @@
 //! ## NS
 //!
 //! ACME Crab will serve a response to `NS` class queries for each FQDN in the config
-//! [`Config::ns_records`][`crate::config::Config::ns_records`] map, returning each of the listed
+//! [`Zone::ns_records`][`crate::config::Zone::ns_records`] map, returning each of the listed
 //! [`LowerName`][`trust_dns_client::rr::LowerName`]s as authoritative answers.
 //!
 //! E.g. with config:
@@ -91,13 +114,64 @@
 //! ns1.pki.example.com.
 //! ```
 //!
+//! ## CAA
+//!
+//! ACME Crab will serve a response to `CAA` class queries for each FQDN in the config
+//! [`Zone::caa_records`][`crate::config::Zone::caa_records`] map, allowing the zone to
+//! restrict which certificate authorities may issue certificates for its names.
+//!
+//! E.g. with config:
+//! ```json
+//! {
+//!   "caa_records": {
+//!     "pki.example.com": [
+//!       { "tag": "issue", "value": "letsencrypt.org" },
+//!       { "tag": "iodef", "value": "mailto:dns-admin@example.com" }
+//!     ]
+//!   },
+//!  ...
+//! }
+//! ```
+//!
+//! A `CAA` class query for `pki.example.com` would return:
+//! ```bash
+//! ❯ dig @127.0.0.1 -p 5353 pki.example.com +short CAA
+//! 0 issue "letsencrypt.org"
+//! 0 iodef "mailto:dns-admin@example.com"
+//! ```
+//!
+//! ## CNAME
+//!
+//! ACME Crab will serve a response to `CNAME` class queries for each alias in the config
+//! [`Zone::cnames`][`crate::config::Zone::cnames`] map. `TXT`, `A`, `AAAA`, `NS`, `SOA` and
+//! `CAA` class queries for an alias return the `CNAME`, followed by the records of the queried
+//! type for the target if it is within the same [`Zone::domain`][`crate::config::Zone::domain`]
+//! zone. Chains of in-zone aliases are followed.
+//!
+//! E.g. with config:
+//! ```json
+//! {
+//!   "cnames": {
+//!     "www.pki.example.com": "pki.example.com"
+//!   },
+//!  ...
+//! }
+//! ```
+//!
+//! A `A` class query for `www.pki.example.com` would return:
+//! ```bash
+//! ❯ dig @127.0.0.1 -p 5353 www.pki.example.com +short A
+//! pki.example.com.
+//! 93.184.216.34
+//! ```
+//!
 //! ## SOA
 //!
-//! ACME Crab will serve a response to `SOA` class queries for
-//! [`Config::domain`][`crate::config::Config::domain`]
-//! using the
-//! [`Config::ns_domain`][`crate::config::Config::ns_domain`] and
-//! [`Config::ns_admin`][`crate::config::Config::ns_admin`] settings.
+//! ACME Crab will serve a response to `SOA` class queries for each
+//! [`Zone::domain`][`crate::config::Zone::domain`]
+//! using its
+//! [`Zone::ns_domain`][`crate::config::Zone::ns_domain`] and
+//! [`Zone::ns_admin`][`crate::config::Zone::ns_admin`] settings.
 //!
 //! E.g. With config:
 //! ```json
@@ -115,9 +189,170 @@
 //! ns1.pki.example.com. dns-admin.example.com. 20230312 86400 7200 3600000 172800
 //! ```
 //!
-//! _Note: The zone serial (`20230312`) will differ based on the date the query is performed._
+//! The zone serial (`20230312`) starts from the date the TXT store was created, and is
+//! incremented each time the TXT store changes (see [`crate::txt_store`]). The counter is
+//! persisted by durable TXT stores, so it never goes backwards across restarts. Setting
+//! [`Config::soa_serial`][`crate::config::Config::soa_serial`] to `date` serves the current date
+//! (`YYYYMMDD`) as the serial instead.
+//!
+//! The refresh, retry, expire and minimum timers (`86400 7200 3600000 172800` above) default to
+//! the values recommended by RIPE-203, and can be set with
+//! [`Zone::soa_timers`][`crate::config::Zone::soa_timers`].
+//!
+//! # TTLs
+//!
+//! Dynamic TXT records and the `SOA` record are served with a TTL of 1 second by default, so
+//! resolvers quickly see new challenge responses. Static A, AAAA, CNAME, CAA and NS records
+//! default to a TTL of 1 hour. Each can be set with
+//! [`Zone::ttls`][`crate::config::Zone::ttls`].
+//!
+//! # Negative Answers
+//!
+//! Queries for names in the zone that have no records of the queried type are answered with
+//! NODATA (`NOERROR` with an empty answer section), e.g. a `TXT` query for an ACL subdomain
+//! (or a name matching an ACL pattern) without any provisioned values, or an `AAAA` query for a
+//! name in [`Zone::addrs`][`crate::config::Zone::addrs`] that only has IPv4 addresses. Queries
+//! for names that don't exist in the zone are answered with `NXDOMAIN`.
+//!
+//! In both cases the zone `SOA` record is included in the authority section, allowing
+//! resolvers to cache the negative answer as described in [RFC-2308][RFC-2308]. Its TTL is
+//! the lesser of the `SOA` TTL and the `SOA` minimum timer.
+//!
+//! Queries for names outside of every zone, e.g. `google.com`, aren't ACME Crab's to answer.
+//! They are answered with `REFUSED`, without the authoritative answer (`AA`) bit set.
+//!
+//! [RFC-2308]: https://www.rfc-editor.org/rfc/rfc2308
+//!
+//! # EDNS(0)
+//!
+//! Responses to requests that include an [RFC-6891][RFC-6891] EDNS(0) `OPT` record include an
+//! `OPT` record advertising the server's maximum UDP payload size,
+//! [`Config::dns_max_udp_payload`][`crate::config::Config::dns_max_udp_payload`]. Requests using a
+//! later EDNS version are answered with `BADVERS`.
+//!
+//! Responses sent over UDP are limited to 512 bytes, or for EDNS(0) clients the lesser of the
+//! client's advertised payload size and the server's maximum. Responses that don't fit are sent
+//! with the `TC` flag set and no records, prompting the client to retry over TCP.
+//!
+//! [RFC-6891]: https://www.rfc-editor.org/rfc/rfc6891
+//!
+//! # Response rate limiting
+//!
+//! Since the source address of a UDP request can be spoofed, an open nameserver can be used to
+//! reflect (and amplify) traffic towards a victim. When
+//! [`Config::rate_limit`][`crate::config::Config::rate_limit`] is set, responses sent over UDP
+//! are rate limited per client network (by default per `/24` for IPv4 and `/56` for IPv6), with
+//! a separate token bucket for answers, `NXDOMAIN`s and errors. Responses over a bucket's rate
+//! are dropped, except that every Nth one "slips" out with the `TC` flag set and no records, so
+//! that legitimate clients sharing the network retry over TCP. Responses over TCP, and to
+//! exempt networks, are never limited. Withheld responses are counted by the
+//! [`acmecrab_dns_rate_limited_total`][crate::metrics] metric.
+//!
+//! # DNSSEC
+//!
+//! When built with the `dnssec` feature and [`Config::dnssec`][`crate::config::Config::dnssec`]
+//! is set, responses to queries with the DNSSEC OK bit are signed online: each set of answer
+//! records gets an RRSIG record made with the zone signing key, and negative answers include
+//! NSEC records proving the name (and any wildcard) or the queried type doesn't exist. The NSEC
+//! records are minimally covering [RFC-4470][RFC-4470] "white lies", so the names in the zone
+//! can't be enumerated by walking them. `DNSKEY` queries for the zone apex are answered with the
+//! key signing and zone signing keys, signed by the key signing key. The DS record to publish in
+//! the parent zone is logged at startup.
+//!
+//! ```bash
+//! ❯ dig @127.0.0.1 -p 5353 +dnssec test.pki.example.com TXT
+//! ```
+//!
+//! Zone transfers aren't signed.
+//!
+//! [RFC-4470]: https://www.rfc-editor.org/rfc/rfc4470
+//!
+//! # AXFR
+//!
+//! Clients in one of the [`Config::transfer_allowed`][`crate::config::Config::transfer_allowed`]
+//! networks can request an [RFC-5936][RFC-5936] zone transfer of a
+//! [`Zone::domain`][`crate::config::Zone::domain`] zone over TCP, e.g. to keep a secondary
+//! nameserver in sync. The transfer includes the static records from the config and the current
+//! dynamic TXT record values, and is bracketed by the zone `SOA` record. Transfer requests over
+//! UDP, or from other addresses, are `REFUSED`.
+//!
+//! ```bash
+//! ❯ dig @127.0.0.1 -p 5353 pki.example.com AXFR +tcp
+//! ```
+//!
+//! [RFC-5936]: https://www.rfc-editor.org/rfc/rfc5936
+//!
+//! # NOTIFY
+//!
+//! Each time a TXT record value is updated or deleted using the [HTTP API][crate::api] (or an
+//! [UPDATE](#update)) the zone `SOA` serial is incremented, and an [RFC-1996][RFC-1996] NOTIFY
+//! message is sent to each of the
+//! [`Config::notify_addrs`][`crate::config::Config::notify_addrs`] secondaries over UDP. This
+//! prompts them to refresh the zone (e.g. with an [AXFR](#axfr)) right away, instead of waiting
+//! for the `SOA` refresh interval. NOTIFY messages that aren't acknowledged are retried with a
+//! backoff. Acknowledgements, and secondaries that never acknowledge, are logged.
+//!
+//! [RFC-1996]: https://www.rfc-editor.org/rfc/rfc1996
+//!
+//! # UPDATE
+//!
+//! TXT records can also be added and deleted with [RFC-2136][RFC-2136] UPDATE messages, for ACME
+//! clients that don't support the [HTTP API][crate::api]. UPDATE messages must be signed with
+//! [TSIG][RFC-8945] using one of the
+//! [`Zone::tsig_keys`][`crate::config::Zone::tsig_keys`], and are answered with a response
+//! signed by the same key. Each key may only change the TXT records of its own subdomains, and
+//! added values must be valid [DNS-01] challenge responses. Prerequisites aren't supported.
+//! Unsigned UPDATEs, and UPDATEs that change other record types, are `REFUSED`. UPDATEs that
+//! change the zone send a [NOTIFY](#notify) like API updates.
+//!
+//! ```bash
+//! ❯ nsupdate -y hmac-sha256:acme-key:<base64 secret> <<EOF
+//! server 127.0.0.1 5353
+//! zone pki.example.com
+//! update add _acme-challenge.www.pki.example.com 1 TXT LPsIwTo7o8BoG0-vjCyGQGBWSVIPxI-i_X336eUOQZo
+//! send
+//! EOF
+//! ```
+//!
+//! [RFC-2136]: https://www.rfc-editor.org/rfc/rfc2136
+//! [RFC-8945]: https://www.rfc-editor.org/rfc/rfc8945
+//!
+//! # DNS-over-TLS
+//!
+//! When built with the `dns-over-tls` feature, ACME Crab can also answer queries over
+//! [RFC-7858][RFC-7858] DNS-over-TLS connections on the
+//! [`Config::dns_tls_bind_addr`][`crate::config::Config::dns_tls_bind_addr`]. Requests over TLS
+//! are answered the same way as requests over TCP. The certificate and key files are checked for
+//! changes periodically, and reloaded so that renewed certificates are served without a restart.
+//!
+//! ```bash
+//! ❯ kdig @127.0.0.1 -p 853 +tls pki.example.com A
+//! ```
+//!
+//! [RFC-7858]: https://www.rfc-editor.org/rfc/rfc7858
+//!
+//! # DNS-over-HTTPS
+//!
+//! Queries can also be sent to the [`/dns-query`][crate::api#dns-query-getpost] HTTP API
+//! endpoint as [RFC-8484][RFC-8484] DNS-over-HTTPS requests. These are answered by the
+//! server's [`DohHandler`] the same way as requests over TCP, sharing the same rate limits and
+//! metrics. The HTTP API only serves plaintext HTTP, so DNS-over-HTTPS clients that require
+//! HTTPS (as RFC 8484 does) must reach the endpoint through a TLS terminating reverse proxy.
+//!
+//! [RFC-8484]: https://www.rfc-editor.org/rfc/rfc8484
 
+#[cfg(feature = "dnssec")]
+mod dnssec;
+mod doh;
 mod handlers;
+pub mod notify;
+mod rate_limit;
 pub mod server;
+#[cfg(feature = "dns-over-tls")]
+mod tls;
+mod tsig;
+mod update;
 
-pub use server::new;
+pub use doh::{DohHandler, DohResponse};
+pub use notify::Notifier;
+pub use server::{new, Drain, Server};
@@ -0,0 +1,160 @@
+use crate::config::{DynConfig, Zone};
+use crate::dns::handlers::Handler;
+use crate::error::Error;
+use crate::txt_store::DynTxtStore;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use trust_dns_server::client::op::{Message, MessageType, OpCode, Query, ResponseCode};
+use trust_dns_server::client::rr::{LowerName, RData, Record, RecordType};
+
+// Number of times a NOTIFY is sent to a secondary before giving up on an acknowledgement.
+const NOTIFY_ATTEMPTS: u32 = 5;
+
+// How long to wait for the first NOTIFY to be acknowledged. Doubled for each retry.
+const NOTIFY_INITIAL_TIMEOUT: Duration = Duration::from_secs(2);
+
+// NOTIFY acknowledgements echo the question and carry no records, so are always small.
+const MAX_ACK_SIZE: usize = 512;
+
+/// `Notifier` sends [RFC-1996][RFC-1996] NOTIFY messages to the secondary nameservers in
+/// [`Config::notify_addrs`][crate::config::Config::notify_addrs] when a zone changes, so they
+/// refresh the zone without waiting for the `SOA` refresh interval.
+///
+/// [RFC-1996]: https://www.rfc-editor.org/rfc/rfc1996
+#[derive(Clone)]
+pub struct Notifier {
+    config: DynConfig,
+    txt_store: DynTxtStore,
+}
+
+impl Notifier {
+    /// Construct a [`Notifier`] that notifies the secondaries in the current [`DynConfig`] of the
+    /// zone serial held by the [`DynTxtStore`].
+    #[must_use]
+    pub fn new(config: DynConfig, txt_store: DynTxtStore) -> Self {
+        Self { config, txt_store }
+    }
+
+    /// Send NOTIFY messages for the current serial of the zone with the given domain to each of
+    /// the [`Config::notify_addrs`][crate::config::Config::notify_addrs] in the background,
+    /// retrying until they are acknowledged. Call after the [`DynTxtStore`] has changed the
+    /// zone's records.
+    pub async fn zone_changed(&self, domain: &LowerName) {
+        let config = self.config.current();
+        if config.notify_addrs.is_empty() {
+            return;
+        }
+        let Some(zone) = config.zone(domain).filter(|zone| zone.domain == *domain) else {
+            return;
+        };
+        let serial = match Handler::serial(&config, &self.txt_store).await {
+            Ok(serial) => serial,
+            Err(err) => {
+                tracing::error!("failed to read zone serial for NOTIFY: {err}");
+                return;
+            }
+        };
+        for &secondary in &config.notify_addrs {
+            tokio::spawn(notify(zone.clone(), secondary, serial));
+        }
+    }
+
+    /// Send NOTIFY messages for every zone, like [`Notifier::zone_changed`]. Call after the
+    /// [`DynTxtStore`] changed without a change to a particular zone, e.g. when expired TXT
+    /// records are purged, since every zone shares the serial it holds.
+    pub async fn zones_changed(&self) {
+        for zone in &self.config.current().zones {
+            self.zone_changed(&zone.domain).await;
+        }
+    }
+}
+
+// Send a NOTIFY for the given serial to the secondary until it is acknowledged, or the attempts
+// are exhausted. Each retry waits twice as long for an acknowledgement as the last.
+async fn notify(zone: Zone, secondary: SocketAddr, serial: u32) {
+    let message = match notify_message(&zone, serial) {
+        Ok(message) => message,
+        Err(err) => {
+            tracing::error!("failed to build NOTIFY for serial {serial}: {err}");
+            return;
+        }
+    };
+
+    let mut timeout = NOTIFY_INITIAL_TIMEOUT;
+    for attempt in 1..=NOTIFY_ATTEMPTS {
+        match send_notify(secondary, &message, timeout).await {
+            Ok(Some(ResponseCode::NoError)) => {
+                tracing::info!("{secondary} acknowledged NOTIFY for serial {serial}");
+                return;
+            }
+            Ok(Some(rcode)) => {
+                tracing::warn!("{secondary} rejected NOTIFY for serial {serial}: {rcode}");
+                return;
+            }
+            Ok(None) => tracing::debug!(
+                "NOTIFY {attempt}/{NOTIFY_ATTEMPTS} to {secondary} for serial {serial} was not acknowledged within {}s",
+                timeout.as_secs()
+            ),
+            Err(err) => tracing::debug!(
+                "NOTIFY {attempt}/{NOTIFY_ATTEMPTS} to {secondary} for serial {serial} failed: {err}"
+            ),
+        }
+        timeout *= 2;
+    }
+    tracing::warn!(
+        "{secondary} did not acknowledge NOTIFY for serial {serial} after {NOTIFY_ATTEMPTS} attempts"
+    );
+}
+
+// Returns a NOTIFY message for the zone, with the new SOA in the answer section as a hint.
+fn notify_message(zone: &Zone, serial: u32) -> Result<Message, Error> {
+    let zone_name = zone.domain.clone().into();
+    let mut message = Message::new();
+    message
+        .set_id(rand::random())
+        .set_message_type(MessageType::Query)
+        .set_op_code(OpCode::Notify)
+        .set_authoritative(true)
+        .add_query(Query::query(zone_name, RecordType::SOA))
+        .add_answer(Record::from_rdata(
+            zone.domain.clone().into(),
+            zone.ttls.soa,
+            RData::SOA(Handler::soa(zone, serial)?),
+        ));
+    Ok(message)
+}
+
+// Send the NOTIFY message to the secondary over UDP and wait for its acknowledgement. Returns
+// the acknowledgement's response code, or None if there was no acknowledgement before the timeout.
+async fn send_notify(
+    secondary: SocketAddr,
+    message: &Message,
+    timeout: Duration,
+) -> Result<Option<ResponseCode>, Error> {
+    let bind_addr: SocketAddr = match secondary {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(bind_addr).await?;
+    socket.connect(secondary).await?;
+    socket.send(&message.to_vec()?).await?;
+
+    let ack = async {
+        let mut buf = [0; MAX_ACK_SIZE];
+        loop {
+            let len = socket.recv(&mut buf).await?;
+            // Ignore anything that isn't a response to this NOTIFY.
+            if let Ok(response) = Message::from_vec(&buf[..len]) {
+                if response.id() == message.id() && response.message_type() == MessageType::Response
+                {
+                    return Ok::<_, Error>(response.response_code());
+                }
+            }
+        }
+    };
+    match tokio::time::timeout(timeout, ack).await {
+        Ok(rcode) => Ok(Some(rcode?)),
+        Err(_) => Ok(None),
+    }
+}
@@ -0,0 +1,184 @@
+use crate::config::Config;
+use crate::metrics;
+use ipnetwork::IpNetwork;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use trust_dns_server::server::{Protocol, Request};
+
+// How often buckets that have refilled are removed, so idle clients don't use memory.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(30);
+
+// Upper bound on the number of buckets tracked. Once reached idle buckets are pruned every
+// BUCKET_CAPACITY, and responses to new client networks aren't limited until there is room.
+const MAX_BUCKETS: usize = 100_000;
+
+// How many seconds of responses a bucket holds. Buckets idle for this long are full.
+const BUCKET_CAPACITY: Duration = Duration::from_secs(1);
+
+/// The kinds of response counted separately by the [`RateLimiter`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub(super) enum ResponseKind {
+    /// A response with records, or a NODATA response.
+    Answer,
+    /// An NXDOMAIN response.
+    NxDomain,
+    /// An error response, e.g. REFUSED.
+    Error,
+}
+
+impl ResponseKind {
+    fn label(self) -> &'static str {
+        match self {
+            ResponseKind::Answer => "answer",
+            ResponseKind::NxDomain => "nxdomain",
+            ResponseKind::Error => "error",
+        }
+    }
+}
+
+/// What to do with a response, as decided by the [`RateLimiter`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(super) enum RateLimitAction {
+    /// Send the response as usual.
+    Send,
+    /// Send the response truncated and without records, so the client retries over TCP.
+    Slip,
+    /// Don't send a response.
+    Drop,
+}
+
+impl RateLimitAction {
+    fn label(self) -> &'static str {
+        match self {
+            RateLimitAction::Send => "send",
+            RateLimitAction::Slip => "slip",
+            RateLimitAction::Drop => "drop",
+        }
+    }
+}
+
+/// `RateLimiter` limits the responses sent over UDP to each client network using a token bucket
+/// per network and [`ResponseKind`]. Buckets refill continuously at the configured
+/// [`RateLimit`][crate::config::RateLimit] rate, up to one second of responses.
+#[derive(Debug, Default)]
+pub(super) struct RateLimiter {
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    buckets: HashMap<(IpNetwork, ResponseKind), Bucket>,
+    last_pruned: Instant,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            buckets: HashMap::default(),
+            last_pruned: Instant::now(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    // Responses limited since the bucket was created, used to slip every Nth one. Buckets are
+    // pruned once idle, so this restarts for each burst of traffic.
+    limited: u32,
+}
+
+impl RateLimiter {
+    /// Decide what to do with a response of the given kind to the request. Only responses sent
+    /// over UDP are limited, since the source address of TCP connections can't be spoofed.
+    pub(super) fn check(
+        &self,
+        config: &Config,
+        request: &Request,
+        kind: ResponseKind,
+    ) -> RateLimitAction {
+        let Some(rate_limit) = &config.rate_limit else {
+            return RateLimitAction::Send;
+        };
+        let client_ip = request.src().ip();
+        if !matches!(request.protocol(), Protocol::Udp) || rate_limit.is_exempt(client_ip) {
+            return RateLimitAction::Send;
+        }
+        let rate = match kind {
+            ResponseKind::Answer => rate_limit.responses_per_second,
+            ResponseKind::NxDomain => rate_limit.nxdomains_per_second,
+            ResponseKind::Error => rate_limit.errors_per_second,
+        };
+        if rate == 0 {
+            return RateLimitAction::Send;
+        }
+        // NB: the config ensures the prefix lengths are valid.
+        let Ok(network) = rate_limit.client_network(client_ip) else {
+            return RateLimitAction::Send;
+        };
+
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        state.prune(now);
+        if state.buckets.len() >= MAX_BUCKETS && !state.buckets.contains_key(&(network, kind)) {
+            return RateLimitAction::Send;
+        }
+        let (rate, capacity) = (
+            f64::from(rate),
+            f64::from(rate) * BUCKET_CAPACITY.as_secs_f64(),
+        );
+        let bucket = state
+            .buckets
+            .entry((network, kind))
+            .or_insert_with(|| Bucket {
+                tokens: capacity,
+                updated: now,
+                limited: 0,
+            });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = elapsed.mul_add(rate, bucket.tokens).min(capacity);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return RateLimitAction::Send;
+        }
+
+        if bucket.limited == 0 {
+            tracing::info!("rate limiting {} responses to {network}", kind.label());
+        }
+        bucket.limited = bucket.limited.saturating_add(1);
+        let action = if rate_limit.slip > 0 && bucket.limited % rate_limit.slip == 0 {
+            RateLimitAction::Slip
+        } else {
+            RateLimitAction::Drop
+        };
+        drop(state);
+        tracing::debug!(
+            "rate limited {} response to {client_ip}: {}",
+            kind.label(),
+            action.label()
+        );
+        metrics::dns_rate_limited(kind.label(), action.label());
+        action
+    }
+}
+
+impl State {
+    // Remove buckets that have been idle long enough to refill, which behave the same as new
+    // buckets. Pruning happens every PRUNE_INTERVAL, or more often when there are too many buckets.
+    fn prune(&mut self, now: Instant) {
+        let interval = if self.buckets.len() < MAX_BUCKETS {
+            PRUNE_INTERVAL
+        } else {
+            BUCKET_CAPACITY
+        };
+        if now.duration_since(self.last_pruned) < interval {
+            return;
+        }
+        self.buckets
+            .retain(|_, bucket| now.duration_since(bucket.updated) < BUCKET_CAPACITY);
+        self.last_pruned = now;
+    }
+}
@@ -1,23 +1,379 @@
-use crate::config::Shared;
+use crate::config::DynConfig;
+use crate::dns::doh::DohHandler;
 use crate::dns::handlers::Handler;
+use crate::dns::notify::Notifier;
+#[cfg(feature = "dns-over-tls")]
+use crate::dns::tls;
 use crate::txt_store::DynTxtStore;
+use futures_util::StreamExt;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::{TcpListener, UdpSocket};
-use trust_dns_server::ServerFuture;
+use tokio::sync::Notify;
+use tokio::task::JoinSet;
+use trust_dns_proto::error::{ProtoError, ProtoErrorKind};
+use trust_dns_proto::iocompat::AsyncIoTokioAsStd;
+use trust_dns_proto::tcp::TcpStream;
+use trust_dns_proto::xfer::{BufDnsStreamHandle, DnsStreamHandle, SerialMessage};
+use trust_dns_server::authority::MessageRequest;
+use trust_dns_server::client::op::{Header, Message, ResponseCode};
+use trust_dns_server::proto::serialize::binary::BinDecodable;
+use trust_dns_server::server::{Protocol, Request, ResponseHandle, TimeoutStream};
 
-/// Construct a server future for a ACME Crab DNS server with the given [Shared] config. The server
-/// will respond to TXT record requests using the [`DynTxtStore`].  
+// The largest UDP request read. UDP datagrams can't be larger, so requests are never truncated.
+const MAX_UDP_REQUEST_SIZE: usize = u16::MAX as usize;
+
+/// `Drain` tracks in-flight DNS requests so that shutdown can stop accepting new requests and
+/// wait for in-flight requests to complete before the [`Server`] is dropped. Clones share the
+/// same state.
+#[derive(Clone, Debug, Default)]
+pub struct Drain {
+    draining: Arc<AtomicBool>,
+    in_flight: Arc<AtomicUsize>,
+    idle: Arc<Notify>,
+}
+
+/// A guard held for the duration of an in-flight DNS request.
+pub(super) struct InFlightGuard(Drain);
+
+impl Drain {
+    /// Stop accepting new DNS requests. The UDP socket and the TCP and TLS listeners are closed,
+    /// and requests received on open connections after draining starts are refused.
+    pub fn start(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+        self.idle.notify_waiters();
+    }
+
+    /// Wait until draining has started and there are no in-flight DNS requests.
+    pub async fn wait(&self) {
+        loop {
+            let idle = self.idle.notified();
+            if self.draining.load(Ordering::SeqCst) && self.in_flight.load(Ordering::SeqCst) == 0 {
+                return;
+            }
+            idle.await;
+        }
+    }
+
+    /// Wait until draining has started.
+    pub(super) async fn started(&self) {
+        loop {
+            let notified = self.idle.notified();
+            if self.draining.load(Ordering::SeqCst) {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Track a new in-flight request until the returned guard is dropped, or return `None` if
+    /// draining has started.
+    pub(super) fn track(&self) -> Option<InFlightGuard> {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let guard = InFlightGuard(self.clone());
+        (!self.draining.load(Ordering::SeqCst)).then_some(guard)
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if self.0.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+/// A running ACME Crab DNS server, answering requests received by its UDP, TCP and TLS
+/// listeners in the background. The listeners stop when the [`Drain`] starts, or when the server
+/// is dropped.
+pub struct Server {
+    handler: Arc<Handler>,
+    listeners: JoinSet<Result<(), ProtoError>>,
+}
+
+impl Server {
+    /// Returns a [`DohHandler`] answering DNS-over-HTTPS requests with the same handler as the
+    /// server's listeners.
+    #[must_use]
+    pub fn doh_handler(&self) -> DohHandler {
+        DohHandler::new(self.handler.clone())
+    }
+
+    /// Wait until one of the listeners stops, e.g. because draining started.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`ProtoError`] if a listener fails, e.g. because its UDP socket was closed.
+    pub async fn block_until_done(mut self) -> Result<(), ProtoError> {
+        match self.listeners.join_next().await {
+            Some(Ok(result)) => result,
+            Some(Err(err)) => Err(format!("DNS listener failed: {err}").into()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Construct a ACME Crab DNS server with the given [`DynConfig`]. The server will respond to TXT
+/// record requests using the [`DynTxtStore`]. Responses always use the current config, while the
+/// bind addresses are only read at construction. In-flight requests are tracked by the [`Drain`]
+/// for graceful shutdown.
 ///
 /// # Errors
 ///
 /// Returns [`crate::error::Error::IO`] if the DNS server UDP or TCP sockets specified in the
-/// [Shared] config can't be bound (e.g. because they are already in use).
-pub async fn new(config: Shared, txt_store: DynTxtStore) -> anyhow::Result<ServerFuture<Handler>> {
-    let udp_addr = config.dns_udp_bind_addr;
-    let tcp_addr = config.dns_tcp_bind_addr;
-    let tcp_timeout = config.dns_tcp_timeout;
-    let dns_handler = Handler::new(config, txt_store);
-    let mut dns_server = ServerFuture::new(dns_handler);
-    dns_server.register_socket(UdpSocket::bind(udp_addr).await?);
-    dns_server.register_listener(TcpListener::bind(tcp_addr).await?, tcp_timeout);
-    Ok(dns_server)
+/// [`DynConfig`] can't be bound (e.g. because they are already in use), or the DNSSEC signing
+/// keys can't be read.
+pub async fn new(
+    config: DynConfig,
+    txt_store: DynTxtStore,
+    notifier: Notifier,
+    drain: Drain,
+) -> anyhow::Result<Server> {
+    let current = config.current();
+    let udp_addr = current.dns_udp_bind_addr;
+    let tcp_addr = current.dns_tcp_bind_addr;
+    let tcp_timeout = current.dns_tcp_timeout;
+    let dns_handler = Arc::new(Handler::new(config, txt_store, notifier, drain.clone())?);
+    #[cfg(feature = "dnssec")]
+    for ds in dns_handler.ds()? {
+        tracing::info!("DNSSEC signing enabled, publish DS record in the parent zone: {ds}");
+    }
+    let mut listeners = JoinSet::new();
+    #[cfg(feature = "dns-over-tls")]
+    if let Some(tls_addr) = current.dns_tls_bind_addr {
+        // NB: the config ensures the certificate and key paths are set with a TLS bind address.
+        let resolver = tls::CertResolver::new(
+            current.dns_tls_cert_path.as_deref().unwrap_or_default(),
+            current.dns_tls_key_path.as_deref().unwrap_or_default(),
+        )?;
+        tokio::spawn(resolver.clone().reload_on_change());
+        listeners.spawn(tls::serve(
+            TcpListener::bind(tls_addr).await?,
+            tcp_timeout,
+            dns_handler.clone(),
+            resolver,
+            drain.clone(),
+        ));
+    }
+    listeners.spawn(serve_udp(
+        UdpSocket::bind(udp_addr).await?,
+        dns_handler.clone(),
+        drain.clone(),
+    ));
+    listeners.spawn(serve_tcp(
+        TcpListener::bind(tcp_addr).await?,
+        tcp_timeout,
+        dns_handler.clone(),
+        drain,
+    ));
+    Ok(Server {
+        handler: dns_handler,
+        listeners,
+    })
+}
+
+// Answer each request received by the UDP socket in its own task, until the socket fails or
+// draining starts. The socket is closed once the responses to in-flight requests are sent.
+async fn serve_udp(
+    socket: UdpSocket,
+    handler: Arc<Handler>,
+    drain: Drain,
+) -> Result<(), ProtoError> {
+    let socket = Arc::new(socket);
+    let mut buf = vec![0; MAX_UDP_REQUEST_SIZE];
+    loop {
+        let (len, src) = tokio::select! {
+            received = socket.recv_from(&mut buf) => received?,
+            () = drain.started() => return Ok(()),
+        };
+        if src.port() == 0 || src.ip().is_unspecified() {
+            tracing::debug!("ignored UDP request from unreachable address {src}");
+            continue;
+        }
+        let message = SerialMessage::new(buf[..len].to_vec(), src);
+        let (handler, socket) = (handler.clone(), socket.clone());
+        tokio::spawn(async move {
+            let (stream_handle, mut receiver) = BufDnsStreamHandle::new(src);
+            handle_message(&handler, &message, Protocol::Udp, stream_handle).await;
+            // NB: every response is in the channel once handle_message returns.
+            while let Ok(Some(response)) = receiver.get_mut().get_mut().try_next() {
+                if let Err(err) = socket.send_to(response.bytes(), src).await {
+                    tracing::debug!("failed to send UDP response to {src}: {err}");
+                }
+            }
+        });
+    }
+}
+
+// Accept TCP connections on the listener until draining starts, answering the requests on each
+// connection in turn. Connections that don't send a request within the timeout are closed.
+async fn serve_tcp(
+    listener: TcpListener,
+    timeout: Duration,
+    handler: Arc<Handler>,
+    drain: Drain,
+) -> Result<(), ProtoError> {
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            () = drain.started() => return Ok(()),
+        };
+        let (tcp_stream, src) = match accepted {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                tracing::debug!("failed to accept DNS TCP connection: {err}");
+                continue;
+            }
+        };
+        let handler = handler.clone();
+        tokio::spawn(async move {
+            let (stream, stream_handle) =
+                TcpStream::from_stream(AsyncIoTokioAsStd(tcp_stream), src);
+            let mut stream = TimeoutStream::new(stream, timeout);
+            while let Some(message) = stream.next().await {
+                match message {
+                    Ok(message) => {
+                        let stream_handle = stream_handle.clone();
+                        handle_message(&handler, &message, Protocol::Tcp, stream_handle).await;
+                    }
+                    Err(err) => {
+                        tracing::debug!("closing DNS TCP connection from {src}: {err}");
+                        return;
+                    }
+                }
+            }
+        });
+    }
+}
+
+// Decode a request and answer it with the handler. The message bytes are passed to the handler
+// with the decoded request. Requests that can't be decoded are answered with FORMERR if their
+// header could be read.
+//
+// Every response has been sent to the stream handle by the time this returns: the handler awaits
+// sending each response message, and the stream handle queues them on an unbounded channel
+// without waiting. The UDP listener relies on this to collect the responses with `try_next`
+// rather than waiting for the channel to close.
+pub(super) async fn handle_message(
+    handler: &Handler,
+    message: &SerialMessage,
+    protocol: Protocol,
+    mut stream_handle: BufDnsStreamHandle,
+) {
+    let src: SocketAddr = message.addr();
+    match MessageRequest::from_bytes(message.bytes()) {
+        Ok(request) => {
+            let request = Request::new(request, src, protocol);
+            let response_handle = ResponseHandle::new(src, stream_handle);
+            handler
+                .handle_request(&request, message.bytes(), response_handle)
+                .await;
+        }
+        Err(err) => {
+            tracing::debug!("invalid {protocol} DNS request from {src}: {err}");
+            if let ProtoErrorKind::FormError { header, .. } = err.kind() {
+                let mut header = Header::response_from_request(header);
+                header.set_response_code(ResponseCode::FormErr);
+                let mut response = Message::new();
+                response.set_header(header);
+                let sent = response
+                    .to_vec()
+                    .and_then(|bytes| stream_handle.send(SerialMessage::new(bytes, src)));
+                if let Err(err) = sent {
+                    tracing::debug!("failed to send FORMERR to {src}: {err}");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::handlers::tests::handler;
+    use std::str::FromStr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use trust_dns_server::client::op::Query;
+    use trust_dns_server::client::rr::{Name, RecordType};
+
+    // How long to wait for a response before failing the test.
+    const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+    // Returns an encoded A query for pki.example.com.
+    fn query() -> Vec<u8> {
+        let mut message = Message::new();
+        message.set_id(1234).add_query(Query::query(
+            Name::from_str("pki.example.com.").unwrap(),
+            RecordType::A,
+        ));
+        message.to_vec().unwrap()
+    }
+
+    fn assert_answered(response: &[u8]) {
+        let response = Message::from_vec(response).unwrap();
+        assert_eq!(response.id(), 1234);
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert_eq!(response.answers().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn udp_requests_are_answered() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let drain = Drain::default();
+        let listener = tokio::spawn(serve_udp(socket, handler(), drain.clone()));
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut buf = vec![0; MAX_UDP_REQUEST_SIZE];
+        client.send_to(&query(), addr).await.unwrap();
+        let len = tokio::time::timeout(RESPONSE_TIMEOUT, client.recv(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_answered(&buf[..len]);
+
+        // A request with a valid header but a missing question is answered with FORMERR.
+        client.send_to(&query()[..12], addr).await.unwrap();
+        let len = tokio::time::timeout(RESPONSE_TIMEOUT, client.recv(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        let response = Message::from_vec(&buf[..len]).unwrap();
+        assert_eq!(response.id(), 1234);
+        assert_eq!(response.response_code(), ResponseCode::FormErr);
+
+        drain.start();
+        listener.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn tcp_requests_are_answered() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let drain = Drain::default();
+        let timeout = Duration::from_secs(60);
+        let listener = tokio::spawn(serve_tcp(listener, timeout, handler(), drain.clone()));
+
+        let mut client = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let request = query();
+        client
+            .write_all(&u16::try_from(request.len()).unwrap().to_be_bytes())
+            .await
+            .unwrap();
+        client.write_all(&request).await.unwrap();
+        let response = tokio::time::timeout(RESPONSE_TIMEOUT, async {
+            let len = client.read_u16().await.unwrap();
+            let mut response = vec![0; usize::from(len)];
+            client.read_exact(&mut response).await.unwrap();
+            response
+        })
+        .await
+        .unwrap();
+        assert_answered(&response);
+
+        drain.start();
+        listener.await.unwrap().unwrap();
+    }
 }
@@ -0,0 +1,188 @@
+use crate::dns::handlers::Handler;
+use crate::dns::server::{handle_message, Drain};
+use crate::error::Error;
+use futures_util::StreamExt;
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::{self, CertifiedKey};
+use rustls::{Certificate, PrivateKey, ServerConfig};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use trust_dns_proto::error::ProtoError;
+use trust_dns_proto::iocompat::AsyncIoTokioAsStd;
+use trust_dns_proto::rustls::tls_from_stream;
+use trust_dns_server::server::{Protocol, TimeoutStream};
+
+// How often the certificate and key files are checked for changes.
+const CERT_RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+
+/// `CertResolver` serves the certificate chain and private key loaded from PEM files for TLS
+/// handshakes. The files are reloaded when they change, so renewed certificates are served
+/// without a restart.
+pub(super) struct CertResolver {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    loaded: RwLock<Loaded>,
+}
+
+// The current certificate and key, and the modification times of the files they were loaded from.
+struct Loaded {
+    modified: (Option<SystemTime>, Option<SystemTime>),
+    certified_key: Arc<CertifiedKey>,
+}
+
+impl CertResolver {
+    /// Load the certificate chain and private key from the given PEM files, or return an error.
+    pub(super) fn new(cert_path: &str, key_path: &str) -> Result<Arc<Self>, Error> {
+        let (cert_path, key_path) = (PathBuf::from(cert_path), PathBuf::from(key_path));
+        let modified = Self::modified(&cert_path, &key_path);
+        let certified_key = Arc::new(Self::load(&cert_path, &key_path)?);
+        Ok(Arc::new(Self {
+            cert_path,
+            key_path,
+            loaded: RwLock::new(Loaded {
+                modified,
+                certified_key,
+            }),
+        }))
+    }
+
+    /// Returns a TLS server config that uses the resolver for every handshake.
+    pub(super) fn server_config(self: &Arc<Self>) -> ServerConfig {
+        ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_cert_resolver(self.clone())
+    }
+
+    /// Periodically check the certificate and key files, reloading them if they have changed.
+    /// If the new files can't be loaded the current certificate continues to be served.
+    pub(super) async fn reload_on_change(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(CERT_RELOAD_INTERVAL);
+        loop {
+            interval.tick().await;
+            let modified = Self::modified(&self.cert_path, &self.key_path);
+            if modified == self.loaded.read().unwrap().modified {
+                continue;
+            }
+            let cert_path = self.cert_path.display();
+            // NB: the new modification times are kept even if loading fails, so a partially
+            //     written pair is retried once the other file changes, rather than every interval.
+            let mut loaded = self.loaded.write().unwrap();
+            loaded.modified = modified;
+            match Self::load(&self.cert_path, &self.key_path) {
+                Ok(certified_key) => {
+                    loaded.certified_key = Arc::new(certified_key);
+                    tracing::info!("reloaded TLS certificate from {cert_path}");
+                }
+                Err(err) => {
+                    tracing::error!("failed to reload TLS certificate from {cert_path}: {err}");
+                }
+            }
+        }
+    }
+
+    fn load(cert_path: &Path, key_path: &Path) -> Result<CertifiedKey, Error> {
+        let mut reader = BufReader::new(File::open(cert_path)?);
+        let certs: Vec<Certificate> = rustls_pemfile::certs(&mut reader)?
+            .into_iter()
+            .map(Certificate)
+            .collect();
+        if certs.is_empty() {
+            return Err(Error::InvalidConfig(format!(
+                "no certificates found in {}",
+                cert_path.display()
+            )));
+        }
+
+        let mut reader = BufReader::new(File::open(key_path)?);
+        let key = rustls_pemfile::read_all(&mut reader)?
+            .into_iter()
+            .find_map(|item| match item {
+                rustls_pemfile::Item::RSAKey(key)
+                | rustls_pemfile::Item::PKCS8Key(key)
+                | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+                _ => None,
+            })
+            .ok_or_else(|| {
+                Error::InvalidConfig(format!("no private key found in {}", key_path.display()))
+            })?;
+        let key = sign::any_supported_type(&key).map_err(|err| {
+            Error::InvalidConfig(format!("invalid private key {}: {err}", key_path.display()))
+        })?;
+
+        Ok(CertifiedKey::new(certs, key))
+    }
+
+    fn modified(cert_path: &Path, key_path: &Path) -> (Option<SystemTime>, Option<SystemTime>) {
+        let modified = |path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        (modified(cert_path), modified(key_path))
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.loaded.read().unwrap().certified_key.clone())
+    }
+}
+
+/// Accept [RFC-7858][RFC-7858] DNS-over-TLS connections on the listener until draining starts,
+/// answering each request on a connection with the handler. Connections that don't complete the
+/// handshake, or send a request, within the timeout are closed.
+///
+/// [RFC-7858]: https://www.rfc-editor.org/rfc/rfc7858
+pub(super) async fn serve(
+    listener: TcpListener,
+    timeout: Duration,
+    handler: Arc<Handler>,
+    resolver: Arc<CertResolver>,
+    drain: Drain,
+) -> Result<(), ProtoError> {
+    let acceptor = TlsAcceptor::from(Arc::new(resolver.server_config()));
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            () = drain.started() => return Ok(()),
+        };
+        let (tcp_stream, src) = match accepted {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                tracing::debug!("failed to accept DNS-over-TLS connection: {err}");
+                continue;
+            }
+        };
+        let (acceptor, handler) = (acceptor.clone(), handler.clone());
+        tokio::spawn(async move {
+            let tls_stream = match tokio::time::timeout(timeout, acceptor.accept(tcp_stream)).await
+            {
+                Ok(Ok(tls_stream)) => tls_stream,
+                Ok(Err(err)) => {
+                    tracing::debug!("TLS handshake with {src} failed: {err}");
+                    return;
+                }
+                Err(_) => {
+                    tracing::debug!("TLS handshake with {src} timed out");
+                    return;
+                }
+            };
+            let (stream, stream_handle) = tls_from_stream(AsyncIoTokioAsStd(tls_stream), src);
+            let mut stream = TimeoutStream::new(stream, timeout);
+            while let Some(message) = stream.next().await {
+                match message {
+                    Ok(message) => {
+                        let stream_handle = stream_handle.clone();
+                        handle_message(&handler, &message, Protocol::Tls, stream_handle).await;
+                    }
+                    Err(err) => {
+                        tracing::debug!("closing DNS-over-TLS connection from {src}: {err}");
+                        return;
+                    }
+                }
+            }
+        });
+    }
+}
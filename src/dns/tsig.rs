@@ -0,0 +1,471 @@
+use crate::config::{TsigAlgorithm, Zone};
+use crate::error::Error;
+use hmac::{Hmac, Mac};
+use sha2::{Sha224, Sha256, Sha384, Sha512};
+use std::fmt;
+use time::OffsetDateTime;
+use trust_dns_proto::error::ProtoResult;
+use trust_dns_proto::rr::rdata::NULL;
+use trust_dns_proto::serialize::binary::{BinDecodable, BinDecoder, BinEncoder};
+use trust_dns_server::client::op::{Header, Query};
+use trust_dns_server::client::rr::{DNSClass, LowerName, Name, RData, Record, RecordType};
+
+// Permitted difference (in seconds) between the time a message was signed and when it is checked.
+const FUDGE: u16 = 300;
+
+/// The TSIG error codes returned for requests that fail verification. See
+/// [RFC-8945 section 5.2](https://www.rfc-editor.org/rfc/rfc8945#section-5.2).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[allow(clippy::enum_variant_names)]
+pub(super) enum TsigError {
+    BadSig,
+    BadKey,
+    BadTime,
+}
+
+impl TsigError {
+    fn code(self) -> u16 {
+        match self {
+            TsigError::BadSig => 16,
+            TsigError::BadKey => 17,
+            TsigError::BadTime => 18,
+        }
+    }
+}
+
+impl fmt::Display for TsigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TsigError::BadSig => "BADSIG",
+            TsigError::BadKey => "BADKEY",
+            TsigError::BadTime => "BADTIME",
+        })
+    }
+}
+
+/// A configured TSIG key used to verify a request and sign its response.
+pub(super) struct SigningKey {
+    algorithm: TsigAlgorithm,
+    secret: Vec<u8>,
+}
+
+/// The TSIG record of a signed request, per
+/// [RFC-8945 section 4.2](https://www.rfc-editor.org/rfc/rfc8945#section-4.2).
+pub(super) struct Tsig {
+    pub(super) key_name: LowerName,
+    algorithm: Name,
+    time_signed: u64,
+    fudge: u16,
+    mac: Vec<u8>,
+    original_id: u16,
+    error: u16,
+    other: Vec<u8>,
+    // The request as it was sent, without its TSIG record and with the original message ID.
+    unsigned_request: Vec<u8>,
+}
+
+impl Tsig {
+    /// Returns the TSIG record of the encoded request message, or None if the request isn't
+    /// signed. The TSIG record is always the last additional record. The request's MAC is
+    /// checked against the message bytes, since re-encoding a parsed message may not reproduce
+    /// them exactly.
+    pub(super) fn from_message(message: &[u8]) -> Result<Option<Self>, Error> {
+        Ok(Self::read(message)?)
+    }
+
+    fn read(message: &[u8]) -> ProtoResult<Option<Self>> {
+        let mut decoder = BinDecoder::new(message);
+        let header = Header::read(&mut decoder)?;
+        if header.additional_count() == 0 {
+            return Ok(None);
+        }
+        for _ in 0..header.query_count() {
+            Query::read(&mut decoder)?;
+        }
+        let records = usize::from(header.answer_count())
+            + usize::from(header.name_server_count())
+            + usize::from(header.additional_count());
+        for _ in 1..records {
+            Record::read(&mut decoder)?;
+        }
+
+        // Read the TSIG record's fields directly, so its RDATA is decoded the same way whether
+        // or not trust-dns was built with its own TSIG support.
+        let tsig_start = decoder.index();
+        let key_name = Name::read(&mut decoder)?;
+        if RecordType::from(decoder.read_u16()?.unverified()) != RecordType::TSIG {
+            return Ok(None);
+        }
+        decoder.read_u16()?; // CLASS
+        decoder.read_u32()?; // TTL
+        let rdata_len = decoder.read_u16()?.unverified();
+        let rdata = decoder.read_slice(usize::from(rdata_len))?.unverified();
+        let mut tsig = Self::decode(key_name.into(), rdata)?;
+
+        // Per RFC 8945 section 4.3.1, the MAC covers the request with the original ID and
+        // without the TSIG record, so ARCOUNT is decremented.
+        let mut unsigned_request = message[..tsig_start].to_vec();
+        unsigned_request[..2].copy_from_slice(&tsig.original_id.to_be_bytes());
+        unsigned_request[10..12].copy_from_slice(&(header.additional_count() - 1).to_be_bytes());
+        tsig.unsigned_request = unsigned_request;
+        Ok(Some(tsig))
+    }
+
+    // Decodes the TSIG RDATA, per RFC 8945 section 4.2.
+    fn decode(key_name: LowerName, rdata: &[u8]) -> ProtoResult<Self> {
+        let mut decoder = BinDecoder::new(rdata);
+        let algorithm = Name::read(&mut decoder)?;
+        let time_high = u64::from(decoder.read_u16()?.unverified());
+        let time_low = u64::from(decoder.read_u32()?.unverified());
+        let fudge = decoder.read_u16()?.unverified();
+        let mac_len = decoder.read_u16()?.unverified();
+        let mac = decoder.read_vec(usize::from(mac_len))?.unverified();
+        let original_id = decoder.read_u16()?.unverified();
+        let error = decoder.read_u16()?.unverified();
+        let other_len = decoder.read_u16()?.unverified();
+        let other = decoder.read_vec(usize::from(other_len))?.unverified();
+
+        Ok(Self {
+            key_name,
+            algorithm,
+            time_signed: time_high << 32 | time_low,
+            fudge,
+            mac,
+            original_id,
+            error,
+            other,
+            unsigned_request: Vec::default(),
+        })
+    }
+
+    /// Returns the key of the zone named by the TSIG record, if it exists and uses the same
+    /// algorithm.
+    pub(super) fn key(&self, zone: &Zone) -> Option<SigningKey> {
+        let key = zone.tsig_key(&self.key_name)?;
+        let algorithm = Name::from_ascii(algorithm_name(key.algorithm)).ok()?;
+        if !algorithm.eq_case(&self.algorithm.to_lowercase()) {
+            return None;
+        }
+        Some(SigningKey {
+            algorithm: key.algorithm,
+            secret: key.secret_bytes().ok()?,
+        })
+    }
+
+    /// Checks the request MAC using the key.
+    pub(super) fn mac_valid(&self, key: &SigningKey) -> Result<bool, Error> {
+        let variables = self.variables(self.time_signed, self.error, &self.other)?;
+        Ok(hmac_valid(
+            key,
+            &[&self.unsigned_request, &variables],
+            &self.mac,
+        ))
+    }
+
+    /// Checks the request was signed within the fudge of the current time.
+    pub(super) fn time_valid(&self) -> bool {
+        let now = now();
+        let fudge = u64::from(self.fudge.min(FUDGE));
+        now.abs_diff(self.time_signed) <= fudge
+    }
+
+    /// Returns the TSIG record for a response to the request. The record is signed with the key
+    /// if provided, otherwise it only carries the error.
+    pub(super) fn response_record(
+        &self,
+        key: Option<&SigningKey>,
+        response: &[u8],
+        error: Option<TsigError>,
+    ) -> Result<Record, Error> {
+        let now = now();
+        let error_code = error.map_or(0, TsigError::code);
+        // A BADTIME response echoes the request's time, and carries the server time as other data.
+        let (time_signed, other) = match error {
+            Some(TsigError::BadTime) => (self.time_signed, now.to_be_bytes()[2..].to_vec()),
+            _ => (now, Vec::default()),
+        };
+        let mac = match key {
+            Some(key) => {
+                let mac_len = u16::try_from(self.mac.len())
+                    .unwrap_or(u16::MAX)
+                    .to_be_bytes();
+                let variables = self.variables(time_signed, error_code, &other)?;
+                hmac(key, &[&mac_len, &self.mac, response, &variables])
+            }
+            None => Vec::default(),
+        };
+
+        let mut rdata = Vec::default();
+        let mut encoder = BinEncoder::new(&mut rdata);
+        self.algorithm
+            .to_lowercase()
+            .emit_as_canonical(&mut encoder, true)?;
+        emit_time(&mut encoder, time_signed)?;
+        encoder.emit_u16(self.fudge)?;
+        emit_data(&mut encoder, &mac)?;
+        encoder.emit_u16(self.original_id)?;
+        encoder.emit_u16(error_code)?;
+        emit_data(&mut encoder, &other)?;
+
+        let mut record = Record::from_rdata(
+            (&self.key_name).into(),
+            0,
+            RData::Unknown {
+                code: RecordType::TSIG.into(),
+                rdata: NULL::with(rdata),
+            },
+        );
+        record.set_dns_class(DNSClass::ANY);
+        Ok(record)
+    }
+
+    // Returns the TSIG variables covered by the MAC, per RFC 8945 section 4.3.3.
+    fn variables(&self, time_signed: u64, error: u16, other: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut bytes = Vec::default();
+        let mut encoder = BinEncoder::new(&mut bytes);
+        Name::from(&self.key_name)
+            .to_lowercase()
+            .emit_as_canonical(&mut encoder, true)?;
+        encoder.emit_u16(DNSClass::ANY.into())?;
+        encoder.emit_u32(0)?;
+        self.algorithm
+            .to_lowercase()
+            .emit_as_canonical(&mut encoder, true)?;
+        emit_time(&mut encoder, time_signed)?;
+        encoder.emit_u16(self.fudge)?;
+        encoder.emit_u16(error)?;
+        emit_data(&mut encoder, other)?;
+        Ok(bytes)
+    }
+}
+
+fn algorithm_name(algorithm: TsigAlgorithm) -> &'static str {
+    match algorithm {
+        TsigAlgorithm::HmacSha224 => "hmac-sha224.",
+        TsigAlgorithm::HmacSha256 => "hmac-sha256.",
+        TsigAlgorithm::HmacSha384 => "hmac-sha384.",
+        TsigAlgorithm::HmacSha512 => "hmac-sha512.",
+    }
+}
+
+// Returns the MAC of the parts using the key.
+fn hmac(key: &SigningKey, parts: &[&[u8]]) -> Vec<u8> {
+    match key.algorithm {
+        TsigAlgorithm::HmacSha224 => keyed::<Hmac<Sha224>>(key, parts)
+            .finalize()
+            .into_bytes()
+            .to_vec(),
+        TsigAlgorithm::HmacSha256 => keyed::<Hmac<Sha256>>(key, parts)
+            .finalize()
+            .into_bytes()
+            .to_vec(),
+        TsigAlgorithm::HmacSha384 => keyed::<Hmac<Sha384>>(key, parts)
+            .finalize()
+            .into_bytes()
+            .to_vec(),
+        TsigAlgorithm::HmacSha512 => keyed::<Hmac<Sha512>>(key, parts)
+            .finalize()
+            .into_bytes()
+            .to_vec(),
+    }
+}
+
+// Checks the MAC of the parts using the key, in constant time.
+fn hmac_valid(key: &SigningKey, parts: &[&[u8]], mac: &[u8]) -> bool {
+    match key.algorithm {
+        TsigAlgorithm::HmacSha224 => keyed::<Hmac<Sha224>>(key, parts).verify_slice(mac),
+        TsigAlgorithm::HmacSha256 => keyed::<Hmac<Sha256>>(key, parts).verify_slice(mac),
+        TsigAlgorithm::HmacSha384 => keyed::<Hmac<Sha384>>(key, parts).verify_slice(mac),
+        TsigAlgorithm::HmacSha512 => keyed::<Hmac<Sha512>>(key, parts).verify_slice(mac),
+    }
+    .is_ok()
+}
+
+// Returns a MAC keyed with the key's secret that has been updated with the parts.
+fn keyed<M: Mac + hmac::digest::KeyInit>(key: &SigningKey, parts: &[&[u8]]) -> M {
+    // NB: HMAC accepts keys of any length.
+    let mut mac = <M as Mac>::new_from_slice(&key.secret).expect("invalid HMAC key length");
+    for part in parts {
+        mac.update(part);
+    }
+    mac
+}
+
+// Returns the current time in seconds since the Unix epoch.
+fn now() -> u64 {
+    u64::try_from(OffsetDateTime::now_utc().unix_timestamp()).unwrap_or_default()
+}
+
+// Emits a 48 bit timestamp.
+fn emit_time(encoder: &mut BinEncoder<'_>, time: u64) -> Result<(), Error> {
+    encoder.emit_vec(&time.to_be_bytes()[2..])?;
+    Ok(())
+}
+
+// Emits data prefixed with its 16 bit length.
+fn emit_data(encoder: &mut BinEncoder<'_>, data: &[u8]) -> Result<(), Error> {
+    encoder.emit_u16(u16::try_from(data.len()).unwrap_or(u16::MAX))?;
+    encoder.emit_vec(data)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use trust_dns_server::client::op::{Message, MessageType, OpCode};
+    use trust_dns_server::client::rr::rdata::TXT;
+    use trust_dns_server::proto::serialize::binary::BinEncodable;
+
+    // Test case 2 from RFC-4231 section 4.3.
+    const KEY: &[u8] = b"Jefe";
+    const DATA: &[u8] = b"what do ya want for nothing?";
+    const HMAC_SHA256: [u8; 32] = [
+        0x5b, 0xdc, 0xc1, 0x46, 0xbf, 0x60, 0x75, 0x4e, 0x6a, 0x04, 0x24, 0x26, 0x08, 0x95, 0x75,
+        0xc7, 0x5a, 0x00, 0x3f, 0x08, 0x9d, 0x27, 0x39, 0x83, 0x9d, 0xec, 0x58, 0xb9, 0x64, 0xec,
+        0x38, 0x43,
+    ];
+
+    fn key() -> SigningKey {
+        SigningKey {
+            algorithm: TsigAlgorithm::HmacSha256,
+            secret: KEY.to_vec(),
+        }
+    }
+
+    #[test]
+    fn hmac_matches_rfc_4231() {
+        assert_eq!(hmac(&key(), &[&DATA[..10], &DATA[10..]]), HMAC_SHA256);
+    }
+
+    // Returns an UPDATE message adding a TXT record, encoded with name compression.
+    fn update_message(id: u16) -> Vec<u8> {
+        let zone = Name::from_ascii("pki.example.com.").unwrap();
+        let fqdn = Name::from_ascii("_acme-challenge.Test.pki.example.com.").unwrap();
+        let mut message = Message::new();
+        message
+            .set_id(id)
+            .set_op_code(OpCode::Update)
+            .add_query(Query::query(zone, RecordType::SOA))
+            .add_name_server(Record::from_rdata(
+                fqdn,
+                60,
+                RData::TXT(TXT::new(vec![String::from_utf8(DATA.to_vec()).unwrap()])),
+            ));
+        message.to_vec().unwrap()
+    }
+
+    // Appends a TSIG record signing the message with the key, as sent with the original ID.
+    fn sign(message: &[u8], key: &SigningKey, original_id: u16, fudge: u16) -> Vec<u8> {
+        let mut tsig = Tsig {
+            key_name: Name::from_ascii("test.key.").unwrap().into(),
+            algorithm: Name::from_ascii(algorithm_name(key.algorithm)).unwrap(),
+            time_signed: now(),
+            fudge,
+            mac: Vec::default(),
+            original_id,
+            error: 0,
+            other: Vec::default(),
+            unsigned_request: Vec::default(),
+        };
+        let variables = tsig.variables(tsig.time_signed, 0, &[]).unwrap();
+        tsig.mac = hmac(key, &[message, &variables]);
+
+        let mut rdata = Vec::default();
+        let mut encoder = BinEncoder::new(&mut rdata);
+        tsig.algorithm
+            .emit_as_canonical(&mut encoder, true)
+            .unwrap();
+        emit_time(&mut encoder, tsig.time_signed).unwrap();
+        encoder.emit_u16(tsig.fudge).unwrap();
+        emit_data(&mut encoder, &tsig.mac).unwrap();
+        encoder.emit_u16(original_id).unwrap();
+        encoder.emit_u16(0).unwrap();
+        emit_data(&mut encoder, &[]).unwrap();
+
+        let mut signed = message.to_vec();
+        signed[11] += 1; // ARCOUNT
+        let mut encoder = BinEncoder::new(&mut signed);
+        encoder.set_offset(message.len());
+        Name::from(&tsig.key_name)
+            .emit_as_canonical(&mut encoder, true)
+            .unwrap();
+        encoder.emit_u16(RecordType::TSIG.into()).unwrap();
+        encoder.emit_u16(DNSClass::ANY.into()).unwrap();
+        encoder.emit_u32(0).unwrap();
+        emit_data(&mut encoder, &rdata).unwrap();
+        signed
+    }
+
+    #[test]
+    fn mac_covers_message_bytes() {
+        let key = key();
+        let mut signed = sign(&update_message(1234), &key, 1234, FUDGE);
+        let tsig = Tsig::from_message(&signed).unwrap().unwrap();
+        assert_eq!(tsig.key_name.to_string(), "test.key.");
+        assert!(tsig.mac_valid(&key).unwrap());
+        assert!(tsig.time_valid());
+
+        // A forwarder may change the message ID, which is restored from the original ID.
+        signed[..2].copy_from_slice(&4321_u16.to_be_bytes());
+        let tsig = Tsig::from_message(&signed).unwrap().unwrap();
+        assert!(tsig.mac_valid(&key).unwrap());
+
+        // Any other change to the message, e.g. to the end of the TXT value, invalidates the MAC.
+        let txt_end = update_message(1234).len() - 1;
+        signed[txt_end] ^= 1;
+        let tsig = Tsig::from_message(&signed).unwrap().unwrap();
+        assert!(!tsig.mac_valid(&key).unwrap());
+
+        assert!(Tsig::from_message(&update_message(1234)).unwrap().is_none());
+    }
+
+    #[test]
+    fn response_echoes_request_fudge() {
+        let key = key();
+        let request = sign(&update_message(1234), &key, 1234, 600);
+        let request_tsig = Tsig::from_message(&request).unwrap().unwrap();
+        assert!(request_tsig.mac_valid(&key).unwrap());
+
+        // Sign a response as the UPDATE handler does, and append the TSIG record to it.
+        let mut response = Message::new();
+        response
+            .set_id(1234)
+            .set_op_code(OpCode::Update)
+            .set_message_type(MessageType::Response);
+        let mut response = response.to_vec().unwrap();
+        let record = request_tsig
+            .response_record(Some(&key), &response, None)
+            .unwrap();
+        response[11] += 1; // ARCOUNT
+        let mut encoder = BinEncoder::new(&mut response);
+        encoder.set_offset(encoder.len());
+        record.emit(&mut encoder).unwrap();
+
+        // The client verifies the response MAC using the fudge from the response TSIG record.
+        let response_tsig = Tsig::from_message(&response).unwrap().unwrap();
+        assert_eq!(response_tsig.fudge, 600);
+        let mac_len = u16::try_from(request_tsig.mac.len()).unwrap().to_be_bytes();
+        let variables = response_tsig
+            .variables(response_tsig.time_signed, response_tsig.error, &[])
+            .unwrap();
+        assert!(hmac_valid(
+            &key,
+            &[
+                &mac_len,
+                &request_tsig.mac,
+                &response_tsig.unsigned_request,
+                &variables
+            ],
+            &response_tsig.mac,
+        ));
+    }
+
+    #[test]
+    fn hmac_valid_rejects_wrong_mac() {
+        assert!(hmac_valid(&key(), &[DATA], &HMAC_SHA256));
+        let mut mac = HMAC_SHA256;
+        mac[31] ^= 1;
+        assert!(!hmac_valid(&key(), &[DATA], &mac));
+        assert!(!hmac_valid(&key(), &[DATA], &HMAC_SHA256[..16]));
+    }
+}
@@ -0,0 +1,354 @@
+use crate::config::{Config, Zone};
+use crate::dns::notify::Notifier;
+use crate::dns::tsig::{SigningKey, Tsig, TsigError};
+use crate::error::Error;
+use crate::metrics;
+use crate::txt_store::{DynTxtStore, TxtChange};
+use trust_dns_proto::serialize::binary::BinEncoder;
+use trust_dns_server::authority::MessageResponseBuilder;
+use trust_dns_server::client::op::{Header, ResponseCode};
+use trust_dns_server::client::rr::rdata::TXT;
+use trust_dns_server::client::rr::{DNSClass, LowerName, RData, RecordType};
+use trust_dns_server::server::{Request, ResponseHandler, ResponseInfo};
+
+// Process an RFC 2136 UPDATE message, given the request and the message bytes it was decoded
+// from. Updates must be signed with one of the TSIG keys of the zone being updated, and may only
+// add and delete the TXT records of the key's subdomains. Responses to signed UPDATEs are signed
+// with the same key.
+pub(super) async fn handle_update<R: ResponseHandler>(
+    config: &Config,
+    txt_store: &DynTxtStore,
+    notifier: &Notifier,
+    request: &Request,
+    message: &[u8],
+    response_handle: R,
+) -> Result<ResponseInfo, Error> {
+    let src = request.src().ip();
+    let tsig = match Tsig::from_message(message) {
+        Ok(Some(tsig)) => tsig,
+        Ok(None) => {
+            tracing::debug!("refused unsigned UPDATE from {src}");
+            return send_unsigned(request, response_handle, ResponseCode::Refused).await;
+        }
+        Err(err) => {
+            tracing::debug!("invalid TSIG in UPDATE from {src}: {err}");
+            return send_unsigned(request, response_handle, ResponseCode::FormErr).await;
+        }
+    };
+
+    // The zone's keys are needed to verify the TSIG, so UPDATEs for other zones aren't signed.
+    let zone_name = request.query().name();
+    let Some(zone) = config
+        .zone(zone_name)
+        .filter(|zone| zone.domain == *zone_name)
+    else {
+        tracing::debug!("refused UPDATE from {src} for unknown zone \"{zone_name}\"");
+        return send_unsigned(request, response_handle, ResponseCode::NotAuth).await;
+    };
+
+    // Reject UPDATEs with an unknown key or invalid MAC without signing the response.
+    let key = match tsig.key(zone) {
+        Some(key) if tsig.mac_valid(&key)? => key,
+        key => {
+            let error = match key {
+                Some(_) => TsigError::BadSig,
+                None => TsigError::BadKey,
+            };
+            tracing::warn!(
+                "rejected UPDATE from {src} with TSIG key \"{}\": {error}",
+                tsig.key_name
+            );
+            return send_tsig_error(&tsig, None, request, response_handle, error).await;
+        }
+    };
+    if !tsig.time_valid() {
+        tracing::warn!(
+            "rejected UPDATE from {src} with TSIG key \"{}\": {}",
+            tsig.key_name,
+            TsigError::BadTime
+        );
+        return send_tsig_error(
+            &tsig,
+            Some(&key),
+            request,
+            response_handle,
+            TsigError::BadTime,
+        )
+        .await;
+    }
+
+    let rcode = match prescan(config, zone, &tsig.key_name, request) {
+        Ok(changes) => {
+            let count = changes.len();
+            apply(txt_store, notifier, zone, changes).await?;
+            tracing::info!(
+                "accepted UPDATE from {src} with TSIG key \"{}\": {} change(s)",
+                tsig.key_name,
+                count
+            );
+            ResponseCode::NoError
+        }
+        Err(rcode) => {
+            tracing::info!(
+                "rejected UPDATE from {src} with TSIG key \"{}\": {rcode}",
+                tsig.key_name
+            );
+            rcode
+        }
+    };
+    let mut header = Header::response_from_request(request.header());
+    header.set_response_code(rcode);
+    send_signed(&tsig, Some(&key), request, response_handle, header, None).await
+}
+
+// Check the zone, prerequisite, and update sections of the UPDATE, returning the TXT changes to
+// apply or the response code to reject the whole UPDATE with. See RFC 2136 section 3.
+fn prescan(
+    config: &Config,
+    zone: &Zone,
+    key_name: &LowerName,
+    request: &Request,
+) -> Result<Vec<TxtChange>, ResponseCode> {
+    let section = request.query();
+    if section.query_type() != RecordType::SOA || section.query_class() != DNSClass::IN {
+        return Err(ResponseCode::FormErr);
+    }
+    // Prerequisites aren't supported.
+    if !request.answers().is_empty() {
+        return Err(ResponseCode::Refused);
+    }
+
+    let mut changes = Vec::default();
+    for record in request.name_servers() {
+        let fqdn = LowerName::from(record.name());
+        // NB: names within a more specific zone belong to that zone instead.
+        if !config
+            .zone(&fqdn)
+            .map_or(false, |fqdn_zone| fqdn_zone.domain == zone.domain)
+        {
+            return Err(ResponseCode::NotZone);
+        }
+        // Deleting all RRsets of a name (type ANY) only deletes its TXT records.
+        let txt_type = record.rr_type() == RecordType::TXT
+            || (record.rr_type() == RecordType::ANY && record.dns_class() == DNSClass::ANY);
+        if !txt_type || !zone.tsig_update_permitted(key_name, &fqdn) {
+            tracing::debug!(
+                "TSIG key \"{key_name}\" is not authorized to update {} \"{fqdn}\"",
+                record.rr_type()
+            );
+            return Err(ResponseCode::Refused);
+        }
+        changes.push(match (record.dns_class(), record.data()) {
+            (DNSClass::IN, Some(RData::TXT(txt))) => {
+                let value = dns01_value(txt).ok_or(ResponseCode::Refused)?;
+                TxtChange::Add(fqdn, value)
+            }
+            (DNSClass::NONE, Some(RData::TXT(txt))) => {
+                let value = dns01_value(txt).ok_or(ResponseCode::Refused)?;
+                TxtChange::Remove(fqdn, Some(value))
+            }
+            (DNSClass::ANY, None) => TxtChange::Remove(fqdn, None),
+            _ => return Err(ResponseCode::FormErr),
+        });
+    }
+    Ok(changes)
+}
+
+// Returns the value of a TXT record with a single string that is a valid DNS-01 challenge
+// response.
+fn dns01_value(txt: &TXT) -> Option<String> {
+    let [value] = txt.txt_data() else {
+        return None;
+    };
+    let value = String::from_utf8(value.to_vec()).ok()?;
+    match crate::api::valid_dns01(&value) {
+        Ok(()) => Some(value),
+        Err(err) => {
+            tracing::debug!("UPDATE TXT value is not a valid DNS-01 response: {err}");
+            None
+        }
+    }
+}
+
+// Apply the TXT changes to the store as a single update, so that either all of them are made
+// or none are (RFC 2136 section 3.4.2). Secondaries are notified if the zone changed.
+async fn apply(
+    txt_store: &DynTxtStore,
+    notifier: &Notifier,
+    zone: &Zone,
+    changes: Vec<TxtChange>,
+) -> Result<(), Error> {
+    let mut txt_store = txt_store.write().await;
+    let modified = metrics::time_store_write("update_txt", txt_store.update_txt(changes)).await?;
+    drop(txt_store);
+    if modified {
+        notifier.zone_changed(&zone.domain).await;
+    }
+    Ok(())
+}
+
+// Send an error response without a TSIG record, for UPDATEs that weren't signed or whose TSIG
+// couldn't be parsed.
+async fn send_unsigned<R: ResponseHandler>(
+    request: &Request,
+    mut response_handle: R,
+    rcode: ResponseCode,
+) -> Result<ResponseInfo, Error> {
+    let builder = MessageResponseBuilder::from_message_request(request);
+    Ok(response_handle
+        .send_response(builder.error_msg(request.header(), rcode))
+        .await?)
+}
+
+// Send a NOTAUTH response for an UPDATE that failed TSIG verification, with the TSIG error.
+async fn send_tsig_error<R: ResponseHandler>(
+    tsig: &Tsig,
+    key: Option<&SigningKey>,
+    request: &Request,
+    response_handle: R,
+    error: TsigError,
+) -> Result<ResponseInfo, Error> {
+    let mut header = Header::response_from_request(request.header());
+    header.set_response_code(ResponseCode::NotAuth);
+    send_signed(tsig, key, request, response_handle, header, Some(error)).await
+}
+
+// Send a response ending with a TSIG record, signed by the key if given. The TSIG record must be
+// the last record of the response, so EDNS isn't echoed.
+async fn send_signed<R: ResponseHandler>(
+    tsig: &Tsig,
+    key: Option<&SigningKey>,
+    request: &Request,
+    mut response_handle: R,
+    header: Header,
+    error: Option<TsigError>,
+) -> Result<ResponseInfo, Error> {
+    // The MAC covers the response without the TSIG record.
+    let mut unsigned = Vec::default();
+    MessageResponseBuilder::from_message_request(request)
+        .build(header, &[], &[], &[], &[])
+        .destructive_emit(&mut BinEncoder::new(&mut unsigned))?;
+    let tsig_record = tsig.response_record(key, &unsigned, error)?;
+
+    let response = MessageResponseBuilder::from_message_request(request).build(
+        header,
+        &[],
+        &[],
+        &[],
+        [&tsig_record],
+    );
+    Ok(response_handle.send_response(response).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::str::FromStr;
+    use trust_dns_server::authority::MessageRequest;
+    use trust_dns_server::client::op::{Message, OpCode, Query};
+    use trust_dns_server::client::rr::{Name, Record};
+    use trust_dns_server::proto::serialize::binary::BinDecodable;
+    use trust_dns_server::server::Protocol;
+
+    const VALUE: &str = "LPsIwTo7o8BoG0-vjCyGQGBWSVIPxI-i_X336eUOQZo";
+
+    // Loads a config for the pki.example.com zone with a TSIG key for _acme-challenge.www.
+    fn config() -> Config {
+        let config = json!({
+            "domain": "pki.example.com",
+            "ns_domain": "ns1.pki.example.com",
+            "ns_admin": "dns-admin@example.com",
+            "api_bind_addr": "127.0.0.1:3000",
+            "api_timeout": 120,
+            "dns_udp_bind_addr": "127.0.0.1:5353",
+            "dns_tcp_bind_addr": "127.0.0.1:5353",
+            "dns_tcp_timeout": 60,
+            "acl": {},
+            "addrs": { "pki.example.com": [ "93.184.216.34" ] },
+            "ns_records": { "pki.example.com": [ "ns1.pki.example.com" ] },
+            "tsig_keys": {
+                "acme-key": {
+                    "algorithm": "hmac-sha256",
+                    "secret": "c2VjcmV0",
+                    "subdomains": [ "_acme-challenge.www" ]
+                }
+            }
+        });
+        let path = std::env::temp_dir().join(format!("acmecrab-{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(&path, config.to_string()).unwrap();
+        let loaded = Config::try_from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        loaded.unwrap()
+    }
+
+    fn txt(name: &str, value: &str) -> Record {
+        Record::from_rdata(
+            Name::from_str(name).unwrap(),
+            60,
+            RData::TXT(TXT::new(vec![value.to_string()])),
+        )
+    }
+
+    // Prescans an UPDATE of the pki.example.com zone with the given update section, signed with
+    // the acme-key TSIG key.
+    fn prescan_updates(updates: Vec<Record>) -> Result<Vec<TxtChange>, ResponseCode> {
+        let config = config();
+        let zone = Name::from_str("pki.example.com.").unwrap();
+        let mut message = Message::new();
+        message
+            .set_op_code(OpCode::Update)
+            .add_query(Query::query(zone.clone(), RecordType::SOA))
+            .add_name_servers(updates);
+        let message = MessageRequest::from_bytes(&message.to_vec().unwrap()).unwrap();
+        let request = Request::new(message, "127.0.0.1:53000".parse().unwrap(), Protocol::Udp);
+        let zone = config.zone(&zone.into()).unwrap();
+        let key_name = LowerName::from_str("acme-key.").unwrap();
+        prescan(&config, zone, &key_name, &request)
+    }
+
+    #[test]
+    fn prescan_accepts_txt_changes() {
+        let fqdn = "_acme-challenge.www.pki.example.com.";
+        let mut delete = txt(fqdn, VALUE);
+        delete.set_dns_class(DNSClass::NONE);
+        let mut delete_all = Record::with(Name::from_str(fqdn).unwrap(), RecordType::ANY, 0);
+        delete_all.set_dns_class(DNSClass::ANY);
+
+        let fqdn = LowerName::from_str(fqdn).unwrap();
+        assert_eq!(
+            prescan_updates(vec![txt(&fqdn.to_string(), VALUE), delete, delete_all]),
+            Ok(vec![
+                TxtChange::Add(fqdn.clone(), VALUE.to_string()),
+                TxtChange::Remove(fqdn.clone(), Some(VALUE.to_string())),
+                TxtChange::Remove(fqdn, None),
+            ])
+        );
+    }
+
+    #[test]
+    fn prescan_rejects_invalid_updates() {
+        let fqdn = "_acme-challenge.www.pki.example.com.";
+        let not_zone = txt("_acme-challenge.www.example.net.", VALUE);
+        let not_txt = Record::from_rdata(
+            Name::from_str(fqdn).unwrap(),
+            60,
+            RData::A("93.184.216.34".parse().unwrap()),
+        );
+        let not_permitted = txt("_acme-challenge.other.pki.example.com.", VALUE);
+        let mut bad_class = txt(fqdn, VALUE);
+        bad_class.set_dns_class(DNSClass::CH);
+        let not_dns01 = txt(fqdn, "not a DNS-01 challenge response");
+
+        for (record, rcode) in [
+            (not_zone, ResponseCode::NotZone),
+            (not_txt, ResponseCode::Refused),
+            (not_permitted, ResponseCode::Refused),
+            (bad_class, ResponseCode::FormErr),
+            (not_dns01, ResponseCode::Refused),
+        ] {
+            // A valid change first must not be applied when a later one is rejected.
+            assert_eq!(prescan_updates(vec![txt(fqdn, VALUE), record]), Err(rcode));
+        }
+    }
+}
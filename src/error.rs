@@ -8,17 +8,40 @@ use trust_dns_server::proto::error::ProtoError;
 /// Error enumerates the possible ACME Crab error states.
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
-    /// Returned when clients `POST` the [`/register` API endpoint][crate::api#register-post].
+    /// Returned when clients `POST` the [`/register` API endpoint][crate::api#register-post]
+    /// without [`Config::registration`][`crate::config::Config::registration`] enabled.
     #[error("not implemented")]
     NotImplemented,
 
+    /// Returned when clients `POST` the [`/update` API endpoint][crate::api#update-post] with
+    /// `X-Api-User` and `X-Api-Key` headers that don't match a registered
+    /// [`Account`][`crate::account::Account`].
+    #[error("invalid credentials for user \"{0}\"")]
+    Unauthorized(String),
+
+    /// Returned when clients `POST` the [`/register` API endpoint][crate::api#register-post]
+    /// with a body that isn't empty, or valid JSON with a list of CIDR networks in `allowfrom`.
+    #[error("invalid registration request: {0}")]
+    InvalidRegistration(#[source] serde_json::Error),
+
     /// Returned when clients `POST` the  [`/update` API endpoint][crate::api#update-post] from
-    /// a source IP address that isn't in a [`Config::acl`][`crate::config::Config::acl`] network,
+    /// a source IP address that isn't in a [`Zone::acl`][`crate::config::Zone::acl`] network,
     /// or when the update specifies a `subdomain` that isn't mentioned in the ACL list for
     /// the client's network.
     #[error("IP {0} is not authorized to update \"{1}\"")]
     AuthForbidden(IpAddr, LowerName),
 
+    /// Returned when clients `POST` the [`/update` API endpoint][crate::api#update-post] or the
+    /// [`/register` API endpoint][crate::api#register-post] with a `zone` that isn't the
+    /// [`Zone::domain`][`crate::config::Zone::domain`] of a configured zone.
+    #[error("unknown zone \"{0}\"")]
+    UnknownZone(String),
+
+    /// Returned when clients `POST` the [`/reload` API endpoint][crate::api#reload-post] from a
+    /// source IP address that isn't a loopback address.
+    #[error("IP {0} is not authorized to reload the config")]
+    ReloadForbidden(IpAddr),
+
     /// Returned when clients `POST` invalid JSON.
     #[error(transparent)]
     JsonExtractorRejection(#[from] JsonRejection),
@@ -33,6 +56,18 @@ pub enum Error {
     #[error("TXT value is not a valid DNS-01 challenge response")]
     InvalidDNS01,
 
+    /// Returned when clients send the [`/dns-query` API endpoint][crate::api#dns-query-getpost]
+    /// a request without a `dns` parameter, or one that isn't a valid base64url encoded DNS
+    /// message.
+    #[error("invalid DNS-over-HTTPS request: {0}")]
+    InvalidDoHRequest(String),
+
+    /// Returned when clients `POST` the
+    /// [`/dns-query` API endpoint][crate::api#dns-query-getpost] with a `Content-Type` other
+    /// than `application/dns-message`.
+    #[error("DNS-over-HTTPS requests must have content type application/dns-message")]
+    InvalidDoHContentType,
+
     /// Returned when a non-fully qualified [`LowerName`][`trust_dns_client::rr::LowerName`] is
     /// provided to [`TxtStore::add_txt`][`crate::txt_store::TxtStore::add_txt`]
     #[error("TXT store key is not a fully qualified name: \"{0}\"")]
@@ -47,6 +82,12 @@ pub enum Error {
     #[error("API bind address ({0}) must be a loopback or private IP")]
     InsecureAPIBind(IpAddr),
 
+    /// Returned when a [`Config`][crate::config::Config] is well-formed JSON, but its values are
+    /// inconsistent (e.g. a [`TxtStoreKind`][crate::config::TxtStoreKind] that requires a
+    /// [`Config::txt_store_state_path`][crate::config::Config::txt_store_state_path] without one).
+    #[error("invalid config: {0}")]
+    InvalidConfig(String),
+
     /// Returned when a generic IO error occurs.
     #[error("an IO error occurred")]
     IO(#[from] std::io::Error),
@@ -58,6 +99,18 @@ pub enum Error {
     #[error("invalid JSON")]
     InvalidJSON(#[from] serde_json::Error),
 
+    /// Returned when the [`SqliteTxtStore`][crate::txt_store::sqlite::SqliteTxtStore] database
+    /// can't be opened, migrated, or queried.
+    #[cfg(feature = "sqlite")]
+    #[error("SQLite error")]
+    Sqlite(#[from] rusqlite::Error),
+
+    /// Returned when a response can't be signed with the
+    /// [`Config::dnssec`][`crate::config::Config::dnssec`] keys.
+    #[cfg(feature = "dnssec")]
+    #[error("DNSSEC error")]
+    Dnssec(#[from] trust_dns_client::rr::dnssec::DnsSecError),
+
     /// Returned when the ACME Crab DNS server encounters a generic DNS protocol error.
     #[error("DNS error")]
     DNSError(#[from] ProtoError),
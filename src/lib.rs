@@ -14,17 +14,19 @@
 //!
 #![warn(clippy::pedantic)]
 
+pub mod account;
 pub mod api;
 pub mod config;
 #[doc(hidden)]
 pub mod crab;
 pub mod dns;
 pub mod error;
+pub mod metrics;
 pub mod txt_store;
 
 use crate::txt_store::{file, memory};
 pub use api::new as new_http;
-pub use config::{Config, Shared};
+pub use config::{Config, DynConfig, Shared};
 pub use dns::new as new_dns;
 pub use file::FileTxtStore;
 pub use memory::InMemoryTxtStore;
@@ -1,13 +1,22 @@
 use acmecrab::error::Error::DNSError;
-use acmecrab::{Config, Shared};
+use acmecrab::DynConfig;
 use anyhow::{anyhow, Result};
 use is_terminal::IsTerminal;
-use std::sync::Arc;
+use std::process::ExitCode;
+use std::time::Duration;
 use tokio::signal;
+use tokio::signal::unix::{signal as unix_signal, SignalKind};
+use tokio::sync::oneshot;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+// Upper bound on how often expired TXT records are purged from the TXT store.
+const MAX_REAP_INTERVAL: Duration = Duration::from_secs(60);
+
+// Exit code used when in-flight requests didn't finish draining before the shutdown timeout.
+const EXIT_FORCED_SHUTDOWN: u8 = 2;
+
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> Result<ExitCode> {
     tracing_init();
 
     let mut first_args = std::env::args().take(2);
@@ -16,39 +25,136 @@ async fn main() -> Result<()> {
         first_args.next(),
     );
 
-    let config = config_init(&program_name, config_file)?;
+    let dyn_config = config_init(&program_name, config_file)?;
+    let config = dyn_config.current();
     let txt_store = config.txt_store().await?;
 
     if std::io::stdout().is_terminal() {
         println!("{}", acmecrab::crab::CRAB);
     }
 
+    let notifier = acmecrab::dns::Notifier::new(dyn_config.clone(), txt_store.clone());
+    if let Some(max_age) = config.txt_max_age {
+        tracing::info!("expiring TXT records after {}s", max_age.as_secs());
+        let reap_interval = max_age.min(MAX_REAP_INTERVAL);
+        tokio::spawn(acmecrab::txt_store::reap_expired(
+            txt_store.clone(),
+            notifier.clone(),
+            reap_interval,
+        ));
+    }
+
     tracing::info!("DNS listening on UDP {}", &config.dns_udp_bind_addr);
     tracing::info!("DNS listening on TCP {}", &config.dns_tcp_bind_addr);
-    let dns_server = acmecrab::dns::new(config.clone(), txt_store.clone()).await?;
-    let dns_handle = tokio::spawn(dns_server.block_until_done());
+    if let Some(dns_tls_bind_addr) = config.dns_tls_bind_addr {
+        tracing::info!("DNS listening on TLS {dns_tls_bind_addr}");
+    }
+    let drain = acmecrab::dns::Drain::default();
+    let dns_server = acmecrab::dns::new(
+        dyn_config.clone(),
+        txt_store.clone(),
+        notifier.clone(),
+        drain.clone(),
+    )
+    .await?;
+    let doh = dns_server.doh_handler();
+    let mut dns_handle = tokio::spawn(dns_server.block_until_done());
 
     tracing::info!("API listening on {}", &config.api_bind_addr);
-    let api_server = acmecrab::api::new(config.clone(), txt_store.clone());
-    let api_handle = tokio::spawn(api_server);
+    let (api_shutdown_tx, api_shutdown_rx) = oneshot::channel::<()>();
+    let api_server = acmecrab::api::new(
+        dyn_config.clone(),
+        txt_store.clone(),
+        notifier,
+        doh,
+        async {
+            api_shutdown_rx.await.ok();
+        },
+    );
+    let mut api_handle = tokio::spawn(api_server);
+
+    let (metrics_shutdown_tx, metrics_shutdown_rx) = oneshot::channel::<()>();
+    let mut metrics_handle = match config.metrics_bind_addr {
+        Some(metrics_bind_addr) => {
+            tracing::info!("metrics listening on {metrics_bind_addr}");
+            tokio::spawn(acmecrab::api::new_metrics(
+                metrics_bind_addr,
+                txt_store.clone(),
+                async {
+                    metrics_shutdown_rx.await.ok();
+                },
+            ))
+        }
+        None => tokio::spawn(std::future::pending()),
+    };
+
+    tokio::spawn(reload_on_sighup(dyn_config.clone()));
 
-    // TODO(XXX): proper graceful shutdown.
     tokio::select! {
-        _ = signal::ctrl_c() => {
-            tracing::info!("quitting from signal");
+        res = shutdown_signal() => {
+            res?;
         },
-        Ok(dns_res) = dns_handle => {
+        Ok(dns_res) = &mut dns_handle => {
             if let Err(err) = dns_res {
                 return Err(DNSError(err).into())
             }
         }
-        Ok(api_res) = api_handle => {
+        Ok(api_res) = &mut api_handle => {
             if let Err(err) = api_res {
                 return Err(err.into())
             }
         }
+        Ok(metrics_res) = &mut metrics_handle => {
+            if let Err(err) = metrics_res {
+                return Err(err.into())
+            }
+        }
+    }
+
+    // Stop accepting new requests, and wait for in-flight requests up to the shutdown timeout.
+    let shutdown_timeout = dyn_config.current().shutdown_timeout;
+    tracing::info!(
+        "draining in-flight requests for up to {}s",
+        shutdown_timeout.as_secs()
+    );
+    api_shutdown_tx.send(()).ok();
+    metrics_shutdown_tx.send(()).ok();
+    drain.start();
+    let drained = tokio::time::timeout(shutdown_timeout, async {
+        (&mut api_handle).await.ok();
+        drain.wait().await;
+    })
+    .await
+    .is_ok();
+    api_handle.abort();
+    metrics_handle.abort();
+    dns_handle.abort();
+
+    if let Err(err) = txt_store.write().await.flush().await {
+        tracing::error!("failed to flush TXT store: {err}");
+    }
+
+    if drained {
+        tracing::info!("goodbye");
+        Ok(ExitCode::SUCCESS)
+    } else {
+        tracing::warn!("in-flight requests did not drain in time, forcing shutdown");
+        Ok(ExitCode::from(EXIT_FORCED_SHUTDOWN))
+    }
+}
+
+// Resolves when SIGINT or SIGTERM is received.
+async fn shutdown_signal() -> Result<()> {
+    let mut terminate = unix_signal(SignalKind::terminate())?;
+    tokio::select! {
+        res = signal::ctrl_c() => {
+            res?;
+            tracing::info!("quitting from SIGINT");
+        },
+        _ = terminate.recv() => {
+            tracing::info!("quitting from SIGTERM");
+        },
     }
-    tracing::info!("goodbye");
     Ok(())
 }
 
@@ -62,13 +168,25 @@ fn tracing_init() {
         .init();
 }
 
-fn config_init(program_name: &str, config_file: Option<String>) -> Result<Shared> {
+fn config_init(program_name: &str, config_file: Option<String>) -> Result<DynConfig> {
     match config_file {
         None => Err(anyhow!("usage: {program_name} /path/to/config.json")),
         Some(config_file) => {
             tracing::debug!("loaded config from {config_file}");
-            let config = Config::try_from_file(&config_file)?;
-            Ok(Arc::new(config))
+            Ok(DynConfig::try_from_file(&config_file)?)
         }
     }
 }
+
+async fn reload_on_sighup(config: DynConfig) -> Result<()> {
+    let mut hangups = unix_signal(SignalKind::hangup())?;
+    while hangups.recv().await.is_some() {
+        tracing::info!("reloading config from SIGHUP");
+        match config.reload() {
+            Ok(changes) if changes.is_empty() => tracing::info!("config reloaded: no changes"),
+            Ok(changes) => tracing::info!("config reloaded: {} change(s)", changes.len()),
+            Err(err) => tracing::error!("config reload failed, keeping current config: {err}"),
+        }
+    }
+    Ok(())
+}
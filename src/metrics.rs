@@ -0,0 +1,164 @@
+//! Prometheus metrics.
+//!
+//! ACME Crab exports the following metrics in the [Prometheus text format][format] from the
+//! [`/metrics` API endpoint][crate::api#metrics-get]:
+//!
+//! * `acmecrab_dns_queries_total` - a counter of [DNS][crate::dns] queries, labelled by the query
+//!   record `type` and the response `rcode`.
+//! * `acmecrab_dns_rate_limited_total` - a counter of [DNS][crate::dns] responses withheld by
+//!   [response rate limiting][crate::dns#response-rate-limiting], labelled by the response
+//!   `kind` (`answer`, `nxdomain` or `error`) and the `action` taken (`drop` or `slip`).
+//! * `acmecrab_api_updates_total` - a counter of [`/update` API][crate::api#update-post] requests,
+//!   labelled by `outcome` (`accepted`, `unauthorized`, `forbidden`, `invalid_dns01` or `error`).
+//! * `acmecrab_txt_store_write_seconds` - a histogram of
+//!   [`TxtStore`][crate::txt_store::TxtStore] write latency, labelled by the write `operation`.
+//! * `acmecrab_txt_records` and `acmecrab_accounts` - gauges of the number of TXT record values
+//!   and registered accounts currently held by the [`TxtStore`][crate::txt_store::TxtStore].
+//!
+//! [format]: https://prometheus.io/docs/instrumenting/exposition_formats/#text-based-format
+
+use crate::error::Error;
+use crate::txt_store::DynTxtStore;
+use lazy_static::lazy_static;
+use prometheus::{
+    HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use std::future::Future;
+use std::io::ErrorKind;
+use std::time::Instant;
+use trust_dns_server::client::op::ResponseCode;
+use trust_dns_server::client::rr::RecordType;
+
+lazy_static! {
+    // NB: unwraps are safe: metric names, labels and registrations are static and known to be
+    //     valid.
+    static ref REGISTRY: Registry = {
+        let registry = Registry::new_custom(Some("acmecrab".to_string()), None).unwrap();
+        registry.register(Box::new(DNS_QUERIES.clone())).unwrap();
+        registry
+            .register(Box::new(DNS_RATE_LIMITED.clone()))
+            .unwrap();
+        registry.register(Box::new(API_UPDATES.clone())).unwrap();
+        registry
+            .register(Box::new(TXT_STORE_WRITE_SECONDS.clone()))
+            .unwrap();
+        registry.register(Box::new(TXT_RECORDS.clone())).unwrap();
+        registry.register(Box::new(ACCOUNTS.clone())).unwrap();
+        registry
+    };
+    static ref DNS_QUERIES: IntCounterVec = IntCounterVec::new(
+        Opts::new("dns_queries_total", "DNS queries by record type and rcode."),
+        &["type", "rcode"],
+    )
+    .unwrap();
+    static ref DNS_RATE_LIMITED: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "dns_rate_limited_total",
+            "DNS responses withheld by rate limiting by kind and action.",
+        ),
+        &["kind", "action"],
+    )
+    .unwrap();
+    static ref API_UPDATES: IntCounterVec = IntCounterVec::new(
+        Opts::new("api_updates_total", "API update requests by outcome."),
+        &["outcome"],
+    )
+    .unwrap();
+    static ref TXT_STORE_WRITE_SECONDS: HistogramVec = HistogramVec::new(
+        HistogramOpts::new(
+            "txt_store_write_seconds",
+            "TXT store write latency by operation.",
+        )
+        .buckets(vec![
+            0.000_1, 0.000_5, 0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0,
+        ]),
+        &["operation"],
+    )
+    .unwrap();
+    static ref TXT_RECORDS: IntGauge =
+        IntGauge::new("txt_records", "TXT record values held by the TXT store.").unwrap();
+    static ref ACCOUNTS: IntGauge =
+        IntGauge::new("accounts", "Registered accounts held by the TXT store.").unwrap();
+}
+
+/// The outcome of an [`/update` API][crate::api#update-post] request.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum UpdateOutcome {
+    /// The TXT record value was updated.
+    Accepted,
+    /// The request had invalid account credentials.
+    Unauthorized,
+    /// The client wasn't permitted to update the subdomain.
+    Forbidden,
+    /// The TXT record value wasn't a valid DNS-01 challenge response.
+    InvalidDNS01,
+    /// The request failed for another reason.
+    Error,
+}
+
+impl UpdateOutcome {
+    fn label(self) -> &'static str {
+        match self {
+            UpdateOutcome::Accepted => "accepted",
+            UpdateOutcome::Unauthorized => "unauthorized",
+            UpdateOutcome::Forbidden => "forbidden",
+            UpdateOutcome::InvalidDNS01 => "invalid_dns01",
+            UpdateOutcome::Error => "error",
+        }
+    }
+}
+
+/// Count a DNS query for the given record type that was answered with the given response code.
+pub fn dns_query(query_type: RecordType, rcode: ResponseCode) {
+    // Label response codes by mnemonic, e.g. NOERROR or NXDOMAIN.
+    let rcode = format!("{rcode:?}").to_uppercase();
+    DNS_QUERIES
+        .with_label_values(&[&query_type.to_string(), &rcode])
+        .inc();
+}
+
+/// Count a DNS response of the given kind that was dropped or slipped by
+/// [response rate limiting][crate::dns#response-rate-limiting].
+pub fn dns_rate_limited(kind: &str, action: &str) {
+    DNS_RATE_LIMITED.with_label_values(&[kind, action]).inc();
+}
+
+/// Count an [`/update` API][crate::api#update-post] request with the given outcome.
+pub fn api_update(outcome: UpdateOutcome) {
+    API_UPDATES.with_label_values(&[outcome.label()]).inc();
+}
+
+/// Await a [`TxtStore`][crate::txt_store::TxtStore] write, observing how long it took under the
+/// given operation name.
+pub async fn time_store_write<T>(operation: &str, write: impl Future<Output = T>) -> T {
+    let start = Instant::now();
+    let result = write.await;
+    TXT_STORE_WRITE_SECONDS
+        .with_label_values(&[operation])
+        .observe(start.elapsed().as_secs_f64());
+    result
+}
+
+/// Render all metrics in the Prometheus text format. The record count gauges are refreshed from
+/// the [`DynTxtStore`] first.
+///
+/// # Errors
+///
+/// Returns [`Error`] if the record counts can't be read from the [`DynTxtStore`].
+///
+/// Returns [`Error::IO`] if the metrics can't be encoded.
+pub async fn render(txt_store: &DynTxtStore) -> Result<String, Error> {
+    {
+        let txt_store = txt_store.read().await;
+        TXT_RECORDS.set(gauge_value(txt_store.txt_count().await?));
+        ACCOUNTS.set(gauge_value(txt_store.account_count().await?));
+    }
+
+    TextEncoder::new()
+        .encode_to_string(&REGISTRY.gather())
+        .map_err(|err| Error::IO(std::io::Error::new(ErrorKind::Other, err)))
+}
+
+fn gauge_value(count: usize) -> i64 {
+    i64::try_from(count).unwrap_or(i64::MAX)
+}
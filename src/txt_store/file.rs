@@ -2,17 +2,22 @@
 //!
 //! Wraps a [`InMemoryTxtStore`][super::memory::InMemoryTxtStore] instance, persisting
 //! updates to a JSON file on disk that can be reloaded across restarts.
+use crate::account::Account;
 use crate::error::Error;
 use crate::txt_store::memory::InMemoryTxtStore;
-use crate::txt_store::TxtStore;
+use crate::txt_store::{TxtChange, TxtStore};
 use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::fs;
 use tokio::fs::File;
 use tokio::io;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::AsyncWriteExt;
 use trust_dns_server::client::rr::LowerName;
 
 /// An file-backed implementation of a dynamic TXT store. After each update a JSON file-on disk is
 /// updated with the new data. This file can be reloaded across restarts to avoid losing state.
+/// Registered [`Account`]s and the zone serial are persisted in the same file.
 ///
 /// Wraps a [`InMemoryTxtStore`][super::memory::InMemoryTxtStore], operating the same way except
 /// for maintaining state beyond in-memory.
@@ -26,6 +31,11 @@ pub struct FileTxtStore {
 impl FileTxtStore {
     /// Save the state of the TXT store as JSON to the store's configured path, or return an Error.
     ///
+    /// The state is written to a temporary file that is synced to disk before it atomically
+    /// replaces the state file, so a crash mid-write never leaves a missing or partially written
+    /// state file. The previous state file is kept as a backup (with a `.bak` suffix) that
+    /// [`FileTxtStore::try_from_file`] falls back to if the state file is missing or corrupt.
+    ///
     /// # Errors
     ///
     /// Returns [`Error::InvalidJSON`] if a record in the store can't be serialized to JSON.
@@ -33,47 +43,112 @@ impl FileTxtStore {
     /// Returns [`Error::IO`] if the serialized TXT store state can't be written to the backing
     /// file path.
     pub async fn save(&self) -> Result<(), Error> {
-        let data = serde_json::to_string_pretty(&self.txt_store)?;
-        let mut output_file = File::create(&self.path).await?;
-        output_file.write_all(data.as_bytes()).await?;
-        output_file.flush().await?;
+        self.write_state(true).await
+    }
+
+    // Serialize the state and write it to the state file, first backing up the previous state
+    // file if `backup` is true.
+    async fn write_state(&self, backup: bool) -> Result<(), Error> {
+        let data = serde_json::to_vec_pretty(&self.txt_store)?;
+        Self::write_atomic(Path::new(&self.path), &data, backup).await?;
         Ok(())
     }
 
     /// Load a [`FileTxtStore`] from the JSON TXT record state located at the given path, or return
-    /// an Error.
+    /// an Error. If `max_age` is provided, TXT values older than the max age will expire.
+    ///
+    /// If the state file is missing or contains invalid JSON, the backup of the previous state
+    /// (with a `.bak` suffix) is loaded instead, and the state file is restored from it. If
+    /// neither exist, an empty state file is created.
     ///
     /// # Errors
     ///
-    /// Returns [`Error::InvalidJSON`] if the JSON state file is invalid.
+    /// Returns [`Error::InvalidJSON`] if the JSON state file is invalid and there is no valid
+    /// backup.
     ///
     /// Returns [`Error::IO`] if the path can't be opened or read.
-    pub async fn try_from_file(p: &str) -> Result<Self, Error> {
-        let contents = match File::open(p).await {
-            Ok(mut f) => {
-                let mut buf = vec![];
-                f.read_to_end(&mut buf).await?;
-                buf
-            }
-            Err(err) => match err.kind() {
-                ErrorKind::NotFound => Self::write_empty_state(File::create(&p).await?).await?,
-                _ => return Err(Error::IO(err)),
+    pub async fn try_from_file(p: &str, max_age: Option<Duration>) -> Result<Self, Error> {
+        let path = Path::new(p);
+        let backup_path = Self::sibling_path(path, "bak");
+
+        let (mut txt_store, recovered) = match Self::read_state(path).await {
+            Ok(Some(txt_store)) => (txt_store, false),
+            Ok(None) => match Self::read_state(&backup_path).await? {
+                Some(txt_store) => {
+                    tracing::warn!("state file {p} is missing, recovered from backup");
+                    (txt_store, true)
+                }
+                None => (InMemoryTxtStore::default(), true),
+            },
+            Err(err @ Error::InvalidJSON(_)) => match Self::read_state(&backup_path).await {
+                Ok(Some(txt_store)) => {
+                    tracing::warn!("state file {p} is corrupt ({err}), recovered from backup");
+                    (txt_store, true)
+                }
+                _ => return Err(err),
             },
+            Err(err) => return Err(err),
         };
 
-        let txt_store: InMemoryTxtStore = serde_json::from_slice(&contents)?;
-        Ok(Self {
+        txt_store.set_max_age(max_age);
+        let store = Self {
             path: p.to_string(),
             txt_store,
-        })
+        };
+        // NB: a corrupt state file must not replace the backup it was recovered from.
+        if recovered {
+            store.write_state(false).await?;
+        }
+        Ok(store)
     }
 
-    async fn write_empty_state(mut f: File) -> io::Result<Vec<u8>> {
-        let default_data = serde_json::to_string_pretty(&InMemoryTxtStore::default())?;
-        let default_bytes = default_data.as_bytes();
-        f.write_all(default_bytes).await?;
-        f.flush().await?;
-        Ok(default_bytes.to_vec())
+    // Read and parse the state at the given path, returning None if the path doesn't exist.
+    async fn read_state(path: &Path) -> Result<Option<InMemoryTxtStore>, Error> {
+        match fs::read(path).await {
+            Ok(contents) => Ok(Some(serde_json::from_slice(&contents)?)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(Error::IO(err)),
+        }
+    }
+
+    // Write data to a temporary file alongside path and sync it to disk. If `backup` is true, any
+    // existing file at path is hard linked to a temporary backup that is renamed over the
+    // previous backup. The temporary file is then renamed over path, so path always holds a
+    // complete state. Finally the containing directory is synced so the renames are durable.
+    async fn write_atomic(path: &Path, data: &[u8], backup: bool) -> io::Result<()> {
+        let tmp_path = Self::sibling_path(path, "tmp");
+        let mut tmp_file = File::create(&tmp_path).await?;
+        tmp_file.write_all(data).await?;
+        tmp_file.sync_all().await?;
+        drop(tmp_file);
+
+        if backup {
+            let backup_tmp_path = Self::sibling_path(path, "bak.tmp");
+            match fs::remove_file(&backup_tmp_path).await {
+                Err(err) if err.kind() != ErrorKind::NotFound => return Err(err),
+                _ => {}
+            }
+            match fs::hard_link(path, &backup_tmp_path).await {
+                Ok(()) => fs::rename(&backup_tmp_path, Self::sibling_path(path, "bak")).await?,
+                Err(err) if err.kind() != ErrorKind::NotFound => return Err(err),
+                Err(_) => {}
+            }
+        }
+        fs::rename(&tmp_path, path).await?;
+
+        let dir = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        File::open(dir).await?.sync_all().await
+    }
+
+    // Returns path with the given extension appended, e.g. "data.json" -> "data.json.bak".
+    fn sibling_path(path: &Path, extension: &str) -> PathBuf {
+        let mut sibling = path.as_os_str().to_owned();
+        sibling.push(".");
+        sibling.push(extension);
+        PathBuf::from(sibling)
     }
 }
 
@@ -85,7 +160,121 @@ impl TxtStore for FileTxtStore {
         Ok(())
     }
 
-    async fn get_txt(&self, fqdn: &LowerName) -> [Option<&String>; 2] {
+    async fn remove_txt(&mut self, fqdn: &LowerName, value: Option<&str>) -> Result<usize, Error> {
+        let removed = self.txt_store.remove_txt(fqdn, value).await?;
+        if removed > 0 {
+            self.save().await?;
+        }
+        Ok(removed)
+    }
+
+    async fn update_txt(&mut self, changes: Vec<TxtChange>) -> Result<bool, Error> {
+        // Keep the previous state so it can be restored if the new state can't be saved.
+        let previous = self.txt_store.clone();
+        let modified = self.txt_store.update_txt(changes).await?;
+        if modified {
+            if let Err(err) = self.save().await {
+                self.txt_store = previous;
+                return Err(err);
+            }
+        }
+        Ok(modified)
+    }
+
+    async fn get_txt(&self, fqdn: &LowerName) -> Result<Vec<String>, Error> {
         self.txt_store.get_txt(fqdn).await
     }
+
+    async fn all_txt(&self) -> Result<Vec<(LowerName, Vec<String>)>, Error> {
+        self.txt_store.all_txt().await
+    }
+
+    async fn purge_expired(&mut self) -> Result<usize, Error> {
+        let purged = self.txt_store.purge_expired().await?;
+        if purged > 0 {
+            self.save().await?;
+        }
+        Ok(purged)
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        self.save().await
+    }
+
+    async fn add_account(&mut self, account: Account) -> Result<(), Error> {
+        self.txt_store.add_account(account).await?;
+        self.save().await?;
+        Ok(())
+    }
+
+    async fn get_account(&self, username: &str) -> Result<Option<Account>, Error> {
+        self.txt_store.get_account(username).await
+    }
+
+    async fn account_exists(&self, fqdn: &LowerName) -> Result<bool, Error> {
+        self.txt_store.account_exists(fqdn).await
+    }
+
+    async fn txt_count(&self) -> Result<usize, Error> {
+        self.txt_store.txt_count().await
+    }
+
+    async fn account_count(&self) -> Result<usize, Error> {
+        self.txt_store.account_count().await
+    }
+
+    async fn serial(&self) -> Result<u32, Error> {
+        self.txt_store.serial().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[tokio::test]
+    async fn corrupt_state_is_recovered_from_backup() {
+        let dir = std::env::temp_dir().join(format!("acmecrab-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        let path = dir.join("data.json");
+        let p = path.to_str().unwrap();
+        let backup_path = FileTxtStore::sibling_path(&path, "bak");
+        let fqdn = LowerName::from_str("test.pki.example.com.").unwrap();
+
+        let mut store = FileTxtStore::try_from_file(p, None).await.unwrap();
+        store
+            .add_txt(fqdn.clone(), "first".to_string())
+            .await
+            .unwrap();
+        store
+            .add_txt(fqdn.clone(), "second".to_string())
+            .await
+            .unwrap();
+        std::fs::write(&path, "{ corrupt").unwrap();
+
+        // The backup holds the state before the second value was added.
+        let mut store = FileTxtStore::try_from_file(p, None).await.unwrap();
+        assert_eq!(store.get_txt(&fqdn).await.unwrap(), vec!["first"]);
+        let backup = FileTxtStore::read_state(&backup_path)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(backup.get_txt(&fqdn).await.unwrap(), vec!["first"]);
+
+        // Saving backs up the recovered state file, never the corrupt one.
+        store
+            .add_txt(fqdn.clone(), "third".to_string())
+            .await
+            .unwrap();
+        let backup = FileTxtStore::read_state(&backup_path)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(backup.get_txt(&fqdn).await.unwrap(), vec!["first"]);
+        let state = FileTxtStore::read_state(&path).await.unwrap().unwrap();
+        assert_eq!(state.get_txt(&fqdn).await.unwrap(), vec!["third", "first"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -1,10 +1,13 @@
 //! An in-memory implementation of the [`TxtStore`][super::TxtStore] trait.
 //!
 //! Makes no effort to persist TXT record values between restarts.
+use crate::account::Account;
 use crate::error::Error;
-use crate::txt_store::TxtStore;
+use crate::txt_store::{counter_serial, date_serial, TxtChange, TxtStore};
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+use time::OffsetDateTime;
 use trust_dns_server::client::rr::LowerName;
 
 /// An in-memory implementation of a dynamic TXT store. TXT values are stored in a [`HashMap`]
@@ -13,9 +16,93 @@ use trust_dns_server::client::rr::LowerName;
 ///
 /// Two TXT records per FQDN is sufficient to solve DNS-01 challenges for the base FQDN identifier
 /// as well as a wildcard FQDN identifier (e.g. `foo.example.com` and `*.foo.example.com`).
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+///
+/// Each value is stored with the time it was added. If the store has a max age, values older
+/// than the max age are never returned and are removed by [`TxtStore::purge_expired`].
+///
+/// Registered [`Account`]s are stored in a separate [`HashMap`] keyed by username.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InMemoryTxtStore {
-    txt_records: HashMap<LowerName, VecDeque<String>>,
+    txt_records: HashMap<LowerName, VecDeque<TxtRecord>>,
+    #[serde(default)]
+    accounts: HashMap<String, Account>,
+    // State files written before the serial was stored start from a date based serial.
+    #[serde(default = "date_serial")]
+    serial: u32,
+    #[serde(skip)]
+    max_age: Option<Duration>,
+}
+
+/// A TXT record value and the time it was added to the store.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(from = "StoredTxtRecord")]
+struct TxtRecord {
+    value: String,
+    #[serde(with = "time::serde::rfc3339")]
+    added: OffsetDateTime,
+}
+
+// State files written before TXT records were timestamped hold bare string values. These are
+// treated as if they were added at load time.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredTxtRecord {
+    Value(String),
+    Record {
+        value: String,
+        #[serde(with = "time::serde::rfc3339")]
+        added: OffsetDateTime,
+    },
+}
+
+impl TxtRecord {
+    fn expired(&self, max_age: Option<Duration>, now: OffsetDateTime) -> bool {
+        max_age.map_or(false, |max_age| now - self.added > max_age)
+    }
+}
+
+impl From<StoredTxtRecord> for TxtRecord {
+    fn from(stored: StoredTxtRecord) -> Self {
+        match stored {
+            StoredTxtRecord::Value(value) => Self {
+                value,
+                added: OffsetDateTime::now_utc(),
+            },
+            StoredTxtRecord::Record { value, added } => Self { value, added },
+        }
+    }
+}
+
+impl Default for InMemoryTxtStore {
+    fn default() -> Self {
+        Self {
+            txt_records: HashMap::default(),
+            accounts: HashMap::default(),
+            serial: date_serial(),
+            max_age: None,
+        }
+    }
+}
+
+impl InMemoryTxtStore {
+    /// Construct an empty [`InMemoryTxtStore`]. If `max_age` is provided, TXT values older than
+    /// the max age will expire.
+    #[must_use]
+    pub fn new(max_age: Option<Duration>) -> Self {
+        Self {
+            max_age,
+            ..Self::default()
+        }
+    }
+
+    /// Set the max age after which TXT values expire. If `None`, TXT values never expire.
+    pub fn set_max_age(&mut self, max_age: Option<Duration>) {
+        self.max_age = max_age;
+    }
+
+    fn bump_serial(&mut self) {
+        self.serial = counter_serial(self.serial).wrapping_add(1);
+    }
 }
 
 #[async_trait::async_trait]
@@ -24,17 +111,196 @@ impl TxtStore for InMemoryTxtStore {
         if !fqdn.is_fqdn() {
             return Err(Error::NotFQDN(fqdn));
         }
+        let record = TxtRecord {
+            value,
+            added: OffsetDateTime::now_utc(),
+        };
         let e = self.txt_records.entry(fqdn).or_default();
-        e.insert(0, value);
+        e.insert(0, record);
         e.truncate(2);
+        self.bump_serial();
+        Ok(())
+    }
+
+    async fn remove_txt(&mut self, fqdn: &LowerName, value: Option<&str>) -> Result<usize, Error> {
+        let Some(records) = self.txt_records.get_mut(fqdn) else {
+            return Ok(0);
+        };
+        let before = records.len();
+        match value {
+            Some(value) => records.retain(|record| record.value != value),
+            None => records.clear(),
+        }
+        let removed = before - records.len();
+        if records.is_empty() {
+            self.txt_records.remove(fqdn);
+        }
+        if removed > 0 {
+            self.bump_serial();
+        }
+        Ok(removed)
+    }
+
+    async fn update_txt(&mut self, changes: Vec<TxtChange>) -> Result<bool, Error> {
+        // Only adding a value for a name that isn't an FQDN can fail, so check every change
+        // before applying any.
+        for change in &changes {
+            if let TxtChange::Add(fqdn, _) = change {
+                if !fqdn.is_fqdn() {
+                    return Err(Error::NotFQDN(fqdn.clone()));
+                }
+            }
+        }
+        let mut modified = false;
+        for change in changes {
+            match change {
+                TxtChange::Add(fqdn, value) => {
+                    self.add_txt(fqdn, value).await?;
+                    modified = true;
+                }
+                TxtChange::Remove(fqdn, value) => {
+                    modified |= self.remove_txt(&fqdn, value.as_deref()).await? > 0;
+                }
+            }
+        }
+        Ok(modified)
+    }
+
+    async fn get_txt(&self, fqdn: &LowerName) -> Result<Vec<String>, Error> {
+        let now = OffsetDateTime::now_utc();
+        Ok(self
+            .txt_records
+            .get(fqdn)
+            .into_iter()
+            .flatten()
+            .filter(|record| !record.expired(self.max_age, now))
+            .map(|record| record.value.clone())
+            .collect())
+    }
+
+    async fn all_txt(&self) -> Result<Vec<(LowerName, Vec<String>)>, Error> {
+        let now = OffsetDateTime::now_utc();
+        Ok(self
+            .txt_records
+            .iter()
+            .map(|(fqdn, records)| {
+                let values = records
+                    .iter()
+                    .filter(|record| !record.expired(self.max_age, now))
+                    .map(|record| record.value.clone())
+                    .collect::<Vec<_>>();
+                (fqdn.clone(), values)
+            })
+            .filter(|(_, values)| !values.is_empty())
+            .collect())
+    }
+
+    async fn purge_expired(&mut self) -> Result<usize, Error> {
+        let now = OffsetDateTime::now_utc();
+        let mut purged = 0;
+        let max_age = self.max_age;
+        self.txt_records.retain(|_, records| {
+            let before = records.len();
+            records.retain(|record| !record.expired(max_age, now));
+            purged += before - records.len();
+            !records.is_empty()
+        });
+        if purged > 0 {
+            self.bump_serial();
+        }
+        Ok(purged)
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
         Ok(())
     }
 
-    async fn get_txt(&self, fqdn: &LowerName) -> [Option<&String>; 2] {
-        let records = self.txt_records.get(fqdn);
-        match records {
-            None => [None, None],
-            Some(records) => [records.front(), records.back()],
+    async fn add_account(&mut self, account: Account) -> Result<(), Error> {
+        if !account.fqdn.is_fqdn() {
+            return Err(Error::NotFQDN(account.fqdn));
         }
+        self.accounts.insert(account.username.clone(), account);
+        self.bump_serial();
+        Ok(())
+    }
+
+    async fn get_account(&self, username: &str) -> Result<Option<Account>, Error> {
+        Ok(self.accounts.get(username).cloned())
+    }
+
+    async fn account_exists(&self, fqdn: &LowerName) -> Result<bool, Error> {
+        Ok(self.accounts.values().any(|account| account.fqdn == *fqdn))
+    }
+
+    async fn txt_count(&self) -> Result<usize, Error> {
+        Ok(self.txt_records.values().map(VecDeque::len).sum())
+    }
+
+    async fn account_count(&self) -> Result<usize, Error> {
+        Ok(self.accounts.len())
+    }
+
+    async fn serial(&self) -> Result<u32, Error> {
+        Ok(counter_serial(self.serial))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[tokio::test]
+    async fn counter_serial_starts_at_date_serial() {
+        let date = date_serial();
+        let stored = |serial: u32| -> InMemoryTxtStore {
+            serde_json::from_value(serde_json::json!({ "txt_records": {}, "serial": serial }))
+                .unwrap()
+        };
+
+        // Counters ahead of the date are kept.
+        let ahead = date.wrapping_add(10);
+        assert_eq!(stored(ahead).serial().await.unwrap(), ahead);
+
+        // Counters behind the date start from it, including counters that are numerically
+        // larger but behind in RFC 1982 serial arithmetic.
+        for behind in [1, date - 1, 4_000_000_000] {
+            let mut store = stored(behind);
+            assert_eq!(store.serial().await.unwrap(), date, "{behind}");
+            let fqdn = LowerName::from_str("test.pki.example.com.").unwrap();
+            store.add_txt(fqdn, "value".to_string()).await.unwrap();
+            assert_eq!(store.serial().await.unwrap(), date + 1, "{behind}");
+        }
+    }
+
+    #[tokio::test]
+    async fn update_txt_is_all_or_nothing() {
+        let mut store = InMemoryTxtStore::default();
+        let fqdn = LowerName::from_str("test.pki.example.com.").unwrap();
+        store
+            .add_txt(fqdn.clone(), "old".to_string())
+            .await
+            .unwrap();
+        let serial = store.serial().await.unwrap();
+
+        let not_fqdn = LowerName::from_str("test.pki.example.com").unwrap();
+        let changes = vec![
+            TxtChange::Remove(fqdn.clone(), None),
+            TxtChange::Add(fqdn.clone(), "new".to_string()),
+            TxtChange::Add(not_fqdn, "new".to_string()),
+        ];
+        assert!(matches!(
+            store.update_txt(changes).await,
+            Err(Error::NotFQDN(_))
+        ));
+        assert_eq!(store.get_txt(&fqdn).await.unwrap(), vec!["old"]);
+        assert_eq!(store.serial().await.unwrap(), serial);
+
+        let changes = vec![
+            TxtChange::Remove(fqdn.clone(), None),
+            TxtChange::Add(fqdn.clone(), "new".to_string()),
+        ];
+        assert!(store.update_txt(changes).await.unwrap());
+        assert_eq!(store.get_txt(&fqdn).await.unwrap(), vec!["new"]);
     }
 }
@@ -1,35 +1,77 @@
 //! Dynamic TXT record storage.
 //!
 //! Supports a generic interface for setting up to two [RFC-8555][RFC-8555] [DNS-01] challenge
-//! response values by FQDN.
+//! response values by FQDN, and for storing the [`Account`]s created by the
+//! [`/register` API endpoint][crate::api#register-post].
 //!
 //! Two implementations are provided, [`memory::InMemoryTxtStore`] and [`file::FileTxtStore`]. The
-//! former is not durable across restarts. The latter will write its state to disk for each update
-//! and load this state again on startup.
+//! former is not durable across restarts. The latter will atomically write its state to disk for
+//! each update and load this state again on startup. With the `sqlite` cargo feature enabled a
+//! third implementation, `sqlite::SqliteTxtStore`, is SQLite-backed and avoids rewriting all
+//! state for each update.
 //!
+//! TXT record values may optionally expire after a max age. Expired values are never returned,
+//! and are periodically removed from the store by the [`reap_expired`] task, which increments the
+//! zone serial and notifies secondaries.
+//!
+//! Each store also holds the zone serial used for the [SOA record][crate::dns#soa]. The serial is
+//! incremented using [RFC-1982][RFC-1982] serial number arithmetic each time the store changes,
+//! and is persisted alongside the TXT records by the durable implementations. New stores start
+//! with a serial based on the current date (`YYYYMMDD`), so the serial never goes backwards
+//! for secondaries that saw a date based serial.
+//!
+//! [RFC-1982]: https://www.rfc-editor.org/rfc/rfc1982
 //! [RFC-8555]: https://www.rfc-editor.org/rfc/rfc8555
 //! [DNS-01]: https://www.rfc-editor.org/rfc/rfc8555#section-8.4
 
+use crate::account::Account;
+use crate::dns::Notifier;
 use crate::error::Error;
+use crate::metrics;
+use lazy_static::lazy_static;
 use std::sync::Arc;
+use std::time::Duration;
+use time::macros::format_description;
+use time::OffsetDateTime;
 use tokio::sync::RwLock;
 use trust_dns_server::client::rr::LowerName;
 
 pub mod file;
 pub mod memory;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+lazy_static! {
+    static ref SERIAL_FORMATTER: &'static [time::format_description::FormatItem<'static>] =
+        format_description!(version = 2, "[year][month][day]");
+}
 
 #[allow(clippy::module_name_repetitions)]
 pub use file::FileTxtStore;
 #[allow(clippy::module_name_repetitions)]
 pub use memory::InMemoryTxtStore;
+#[cfg(feature = "sqlite")]
+#[allow(clippy::module_name_repetitions)]
+pub use sqlite::SqliteTxtStore;
 
 /// `DynTxtStore` is a type alias for a [`TxtStore`] that can be used by multiple read/write
 /// consumers that coordinate through an [`Arc`] and a [`RwLock`] wrapping the [`TxtStore`].
 #[allow(clippy::module_name_repetitions)]
 pub type DynTxtStore = Arc<RwLock<dyn TxtStore + Send + Sync>>;
 
+/// A change to the TXT record values of an FQDN, applied with [`TxtStore::update_txt`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[allow(clippy::module_name_repetitions)]
+pub enum TxtChange {
+    /// Add the TXT record value for the FQDN.
+    Add(LowerName, String),
+    /// Remove the TXT record value for the FQDN, or all of its values if `None`.
+    Remove(LowerName, Option<String>),
+}
+
 /// An async trait describing dynamic storage of [RFC-8555][RFC-8555] [DNS-01] challenge response
 /// values, keyed by the FQDN they should be served for in the [DNS API][crate::dns].
+/// Values can be removed once a challenge has been validated.
 ///
 /// [RFC-8555]: https://www.rfc-editor.org/rfc/rfc8555
 /// [DNS-01]: https://www.rfc-editor.org/rfc/rfc8555#section-8.4
@@ -38,6 +80,91 @@ pub trait TxtStore {
     /// Add a TXT record value for the given FQDN.
     async fn add_txt(&mut self, fqdn: LowerName, value: String) -> Result<(), Error>;
 
-    /// Get the TXT record values for the given FQDN (if any).
-    async fn get_txt(&self, fqdn: &LowerName) -> [Option<&String>; 2];
+    /// Remove the TXT record value for the given FQDN, or all TXT record values for the FQDN if
+    /// `value` is `None`. Returns the number of values removed.
+    async fn remove_txt(&mut self, fqdn: &LowerName, value: Option<&str>) -> Result<usize, Error>;
+
+    /// Apply the changes in order as a single update: if any change fails, none are applied.
+    /// Returns true if any value was added or removed.
+    async fn update_txt(&mut self, changes: Vec<TxtChange>) -> Result<bool, Error>;
+
+    /// Get the unexpired TXT record values for the given FQDN (if any), newest first.
+    async fn get_txt(&self, fqdn: &LowerName) -> Result<Vec<String>, Error>;
+
+    /// Get the unexpired TXT record values for every FQDN that has any, newest first.
+    async fn all_txt(&self) -> Result<Vec<(LowerName, Vec<String>)>, Error>;
+
+    /// Remove all expired TXT record values, returning the number of values removed.
+    async fn purge_expired(&mut self) -> Result<usize, Error>;
+
+    /// Flush any buffered state to durable storage (if any).
+    async fn flush(&mut self) -> Result<(), Error>;
+
+    /// Add a registered [`Account`].
+    async fn add_account(&mut self, account: Account) -> Result<(), Error>;
+
+    /// Get the registered [`Account`] with the given username (if any).
+    async fn get_account(&self, username: &str) -> Result<Option<Account>, Error>;
+
+    /// Returns true if a registered [`Account`] exists for the given FQDN.
+    async fn account_exists(&self, fqdn: &LowerName) -> Result<bool, Error>;
+
+    /// Returns the number of TXT record values held by the store, including any expired values
+    /// that haven't been purged yet.
+    async fn txt_count(&self) -> Result<usize, Error>;
+
+    /// Returns the number of registered [`Account`]s held by the store.
+    async fn account_count(&self) -> Result<usize, Error>;
+
+    /// Returns the zone serial. It is incremented (modulo 2^32) each time a TXT record value or
+    /// [`Account`] is added, or TXT record values are removed. It never falls behind the date
+    /// based serial (`YYYYMMDD`), so switching from date based serials never lowers the serial.
+    async fn serial(&self) -> Result<u32, Error>;
+}
+
+// Returns a serial based on the current date, e.g. 20230312 on March 12th 2023.
+pub(crate) fn date_serial() -> u32 {
+    // NB: unwraps are safe: known date format producing values that will always parse as u32.
+    OffsetDateTime::now_utc()
+        .format(&SERIAL_FORMATTER)
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+// Returns the counter serial for a stored serial: the later of the stored serial and the date
+// based serial, so the counter starts at no less than any date based serial served before it.
+pub(crate) fn counter_serial(stored: u32) -> u32 {
+    later_serial(stored, date_serial())
+}
+
+// Returns the later of two serials, compared with RFC 1982 serial number arithmetic: a serial is
+// later if it is ahead of the other by less than 2^31 (modulo 2^32).
+fn later_serial(a: u32, b: u32) -> u32 {
+    if a.wrapping_sub(b) < 1 << 31 {
+        a
+    } else {
+        b
+    }
+}
+
+/// Periodically remove expired TXT record values from the [`DynTxtStore`] every `interval`.
+/// Removing values increments the zone serial, so the [`Notifier`] is told the zones changed.
+/// Runs until the task is dropped.
+pub async fn reap_expired(txt_store: DynTxtStore, notifier: Notifier, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let mut write_store = txt_store.write().await;
+        let purged = metrics::time_store_write("purge_expired", write_store.purge_expired()).await;
+        drop(write_store);
+        match purged {
+            Ok(0) => {}
+            Ok(purged) => {
+                tracing::info!("purged {purged} expired TXT record value(s)");
+                notifier.zones_changed().await;
+            }
+            Err(err) => tracing::error!("failed to purge expired TXT record values: {err}"),
+        }
+    }
 }
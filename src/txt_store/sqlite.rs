@@ -0,0 +1,367 @@
+//! An SQLite-backed implementation of the [`TxtStore`][super::TxtStore] trait.
+//!
+//! Suited to instances issuing a large number of certificates, where rewriting the entire
+//! [`FileTxtStore`][super::file::FileTxtStore] JSON state for each update becomes expensive.
+//! Only available with the `sqlite` cargo feature.
+use crate::account::Account;
+use crate::error::Error;
+use crate::txt_store::{counter_serial, TxtChange, TxtStore};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use time::OffsetDateTime;
+use trust_dns_server::client::rr::LowerName;
+
+// Schema migrations, applied in order. The database `user_version` records how many have been
+// applied. Never edit a released migration, append a new one instead.
+const MIGRATIONS: &[&str] = &[
+    // 1: Initial schema.
+    "CREATE TABLE txt_records (
+         id INTEGER PRIMARY KEY AUTOINCREMENT,
+         fqdn TEXT NOT NULL,
+         value TEXT NOT NULL,
+         added INTEGER NOT NULL
+     );
+     CREATE INDEX txt_records_fqdn ON txt_records (fqdn, id);
+     CREATE INDEX txt_records_added ON txt_records (added);
+     CREATE TABLE accounts (
+         username TEXT PRIMARY KEY,
+         password_digest TEXT NOT NULL,
+         subdomain TEXT NOT NULL,
+         fqdn TEXT NOT NULL,
+         allow_from TEXT NOT NULL
+     );
+     CREATE INDEX accounts_fqdn ON accounts (fqdn);",
+    // 2: Zone serial, starting from a date based serial.
+    "CREATE TABLE zone_serial (serial INTEGER NOT NULL);
+     INSERT INTO zone_serial (serial) VALUES (CAST(strftime('%Y%m%d', 'now') AS INTEGER));",
+];
+
+// Maximum number of TXT values kept per FQDN, matching the other TxtStore implementations.
+const MAX_TXT_VALUES: i64 = 2;
+
+/// An SQLite-backed implementation of a dynamic TXT store. TXT values are stored in a table
+/// indexed by FQDN, with each update applied in a transaction. Up to two TXT values are kept per
+/// FQDN, the oldest being removed when a third is added. The zone serial is kept in its own
+/// table, and incremented in the same transaction as each change.
+///
+/// Database access is performed on the blocking thread pool.
+#[derive(Debug, Clone)]
+#[allow(clippy::module_name_repetitions)]
+pub struct SqliteTxtStore {
+    conn: Arc<Mutex<Connection>>,
+    max_age: Option<Duration>,
+}
+
+impl SqliteTxtStore {
+    /// Open (or create) the SQLite-backed database at the given path, applying any pending schema
+    /// migrations. If `max_age` is provided, TXT values older than the max age will expire.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Sqlite`] if the database can't be opened or migrated.
+    pub async fn try_from_file(p: &str, max_age: Option<Duration>) -> Result<Self, Error> {
+        let p = p.to_string();
+        let conn = tokio::task::spawn_blocking(move || -> Result<Connection, Error> {
+            let mut conn = Connection::open(p)?;
+            conn.pragma_update(None, "journal_mode", "WAL")?;
+            Self::migrate(&mut conn)?;
+            Ok(conn)
+        })
+        .await
+        .map_err(|err| Error::IO(err.into()))??;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+            max_age,
+        })
+    }
+
+    fn migrate(conn: &mut Connection) -> Result<(), Error> {
+        let applied: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        for (version, migration) in MIGRATIONS.iter().enumerate().skip(applied) {
+            tracing::info!("applying sqlite txt store migration {}", version + 1);
+            let tx = conn.transaction()?;
+            tx.execute_batch(migration)?;
+            tx.pragma_update(None, "user_version", version + 1)?;
+            tx.commit()?;
+        }
+        Ok(())
+    }
+
+    // Run the given closure with the database connection on the blocking thread pool.
+    async fn with_conn<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T, Error> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            // NB: a poisoned lock only means another query panicked, the connection is still
+            //     usable.
+            let mut conn = conn
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner);
+            f(&mut conn)
+        })
+        .await
+        .map_err(|err| Error::IO(err.into()))?
+    }
+
+    // Increment the zone serial, wrapping at 2^32. Called within the transaction making a change.
+    fn bump_serial(conn: &Connection) -> Result<(), Error> {
+        let serial = counter_serial(Self::stored_serial(conn)?).wrapping_add(1);
+        conn.execute("UPDATE zone_serial SET serial = ?1", params![serial])?;
+        Ok(())
+    }
+
+    fn stored_serial(conn: &Connection) -> Result<u32, Error> {
+        Ok(conn.query_row("SELECT serial FROM zone_serial", [], |row| row.get(0))?)
+    }
+
+    // Insert a TXT value for the FQDN, removing its oldest values beyond the maximum. Called
+    // within the transaction making the change.
+    fn insert_txt(conn: &Connection, fqdn: &str, value: &str, added: i64) -> Result<(), Error> {
+        conn.execute(
+            "INSERT INTO txt_records (fqdn, value, added) VALUES (?1, ?2, ?3)",
+            params![fqdn, value, added],
+        )?;
+        conn.execute(
+            "DELETE FROM txt_records WHERE fqdn = ?1 AND id NOT IN (
+                 SELECT id FROM txt_records WHERE fqdn = ?1 ORDER BY id DESC LIMIT ?2
+             )",
+            params![fqdn, MAX_TXT_VALUES],
+        )?;
+        Self::bump_serial(conn)
+    }
+
+    // Delete the TXT value for the FQDN, or all of its values if `value` is None, returning the
+    // number deleted. Called within the transaction making the change.
+    fn delete_txt(conn: &Connection, fqdn: &str, value: Option<&str>) -> Result<usize, Error> {
+        let removed = match value {
+            Some(value) => conn.execute(
+                "DELETE FROM txt_records WHERE fqdn = ?1 AND value = ?2",
+                params![fqdn, value],
+            )?,
+            None => conn.execute("DELETE FROM txt_records WHERE fqdn = ?1", params![fqdn])?,
+        };
+        if removed > 0 {
+            Self::bump_serial(conn)?;
+        }
+        Ok(removed)
+    }
+
+    // Unix timestamp before which TXT values are expired.
+    fn expiry_cutoff(&self) -> i64 {
+        self.max_age.map_or(i64::MIN, |max_age| {
+            (OffsetDateTime::now_utc() - max_age).unix_timestamp()
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl TxtStore for SqliteTxtStore {
+    async fn add_txt(&mut self, fqdn: LowerName, value: String) -> Result<(), Error> {
+        if !fqdn.is_fqdn() {
+            return Err(Error::NotFQDN(fqdn));
+        }
+        let fqdn = fqdn.to_string();
+        let added = OffsetDateTime::now_utc().unix_timestamp();
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            Self::insert_txt(&tx, &fqdn, &value, added)?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn remove_txt(&mut self, fqdn: &LowerName, value: Option<&str>) -> Result<usize, Error> {
+        let fqdn = fqdn.to_string();
+        let value = value.map(ToString::to_string);
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            let removed = Self::delete_txt(&tx, &fqdn, value.as_deref())?;
+            tx.commit()?;
+            Ok(removed)
+        })
+        .await
+    }
+
+    async fn update_txt(&mut self, changes: Vec<TxtChange>) -> Result<bool, Error> {
+        let added = OffsetDateTime::now_utc().unix_timestamp();
+        self.with_conn(move |conn| {
+            // NB: the transaction is rolled back when dropped without being committed.
+            let tx = conn.transaction()?;
+            let mut modified = false;
+            for change in changes {
+                match change {
+                    TxtChange::Add(fqdn, value) => {
+                        if !fqdn.is_fqdn() {
+                            return Err(Error::NotFQDN(fqdn));
+                        }
+                        Self::insert_txt(&tx, &fqdn.to_string(), &value, added)?;
+                        modified = true;
+                    }
+                    TxtChange::Remove(fqdn, value) => {
+                        modified |= Self::delete_txt(&tx, &fqdn.to_string(), value.as_deref())? > 0;
+                    }
+                }
+            }
+            tx.commit()?;
+            Ok(modified)
+        })
+        .await
+    }
+
+    async fn get_txt(&self, fqdn: &LowerName) -> Result<Vec<String>, Error> {
+        let fqdn = fqdn.to_string();
+        let cutoff = self.expiry_cutoff();
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare_cached(
+                "SELECT value FROM txt_records WHERE fqdn = ?1 AND added >= ?2
+                 ORDER BY id DESC LIMIT ?3",
+            )?;
+            let values = stmt
+                .query_map(params![fqdn, cutoff, MAX_TXT_VALUES], |row| row.get(0))?
+                .collect::<Result<_, _>>()?;
+            Ok(values)
+        })
+        .await
+    }
+
+    async fn all_txt(&self) -> Result<Vec<(LowerName, Vec<String>)>, Error> {
+        let cutoff = self.expiry_cutoff();
+        let rows: Vec<(String, String)> = self
+            .with_conn(move |conn| {
+                let mut stmt = conn.prepare_cached(
+                    "SELECT fqdn, value FROM txt_records WHERE added >= ?1
+                     ORDER BY fqdn, id DESC",
+                )?;
+                let rows = stmt
+                    .query_map(params![cutoff], |row| Ok((row.get(0)?, row.get(1)?)))?
+                    .collect::<Result<_, _>>()?;
+                Ok(rows)
+            })
+            .await?;
+
+        let mut all_txt: Vec<(LowerName, Vec<String>)> = Vec::default();
+        for (fqdn, value) in rows {
+            let fqdn = LowerName::from_str(&fqdn)?;
+            match all_txt.last_mut() {
+                Some((last, values)) if *last == fqdn => values.push(value),
+                _ => all_txt.push((fqdn, vec![value])),
+            }
+        }
+        Ok(all_txt)
+    }
+
+    async fn purge_expired(&mut self) -> Result<usize, Error> {
+        let cutoff = self.expiry_cutoff();
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            let purged = tx.execute("DELETE FROM txt_records WHERE added < ?1", params![cutoff])?;
+            if purged > 0 {
+                Self::bump_serial(&tx)?;
+            }
+            tx.commit()?;
+            Ok(purged)
+        })
+        .await
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        // Every update is committed in its own transaction.
+        Ok(())
+    }
+
+    async fn add_account(&mut self, account: Account) -> Result<(), Error> {
+        if !account.fqdn.is_fqdn() {
+            return Err(Error::NotFQDN(account.fqdn));
+        }
+        let allow_from = serde_json::to_string(&account.allow_from)?;
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute(
+                "INSERT INTO accounts (username, password_digest, subdomain, fqdn, allow_from)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    account.username,
+                    account.password_digest,
+                    account.subdomain.to_string(),
+                    account.fqdn.to_string(),
+                    allow_from,
+                ],
+            )?;
+            Self::bump_serial(&tx)?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_account(&self, username: &str) -> Result<Option<Account>, Error> {
+        let username = username.to_string();
+        let row = self
+            .with_conn(move |conn| {
+                Ok(conn
+                    .query_row(
+                        "SELECT username, password_digest, subdomain, fqdn, allow_from
+                         FROM accounts WHERE username = ?1",
+                        params![username],
+                        |row| {
+                            Ok((
+                                row.get::<_, String>(0)?,
+                                row.get::<_, String>(1)?,
+                                row.get::<_, String>(2)?,
+                                row.get::<_, String>(3)?,
+                                row.get::<_, String>(4)?,
+                            ))
+                        },
+                    )
+                    .optional()?)
+            })
+            .await?;
+        let Some((username, password_digest, subdomain, fqdn, allow_from)) = row else {
+            return Ok(None);
+        };
+        Ok(Some(Account {
+            username,
+            password_digest,
+            subdomain: LowerName::from_str(&subdomain)?,
+            fqdn: LowerName::from_str(&fqdn)?,
+            allow_from: serde_json::from_str(&allow_from)?,
+        }))
+    }
+
+    async fn account_exists(&self, fqdn: &LowerName) -> Result<bool, Error> {
+        let fqdn = fqdn.to_string();
+        self.with_conn(move |conn| {
+            Ok(conn.query_row(
+                "SELECT EXISTS (SELECT 1 FROM accounts WHERE fqdn = ?1)",
+                params![fqdn],
+                |row| row.get(0),
+            )?)
+        })
+        .await
+    }
+
+    async fn txt_count(&self) -> Result<usize, Error> {
+        self.with_conn(|conn| {
+            Ok(conn.query_row("SELECT COUNT(*) FROM txt_records", [], |row| row.get(0))?)
+        })
+        .await
+    }
+
+    async fn account_count(&self) -> Result<usize, Error> {
+        self.with_conn(|conn| {
+            Ok(conn.query_row("SELECT COUNT(*) FROM accounts", [], |row| row.get(0))?)
+        })
+        .await
+    }
+
+    async fn serial(&self) -> Result<u32, Error> {
+        self.with_conn(|conn| Ok(counter_serial(Self::stored_serial(conn)?)))
+            .await
+    }
+}